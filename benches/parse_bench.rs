@@ -0,0 +1,120 @@
+use browscap_rs::{MatchEngine, Scratch, UserAgentParser};
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{BufRead, BufReader};
+
+fn load_user_agents() -> Vec<String> {
+    let file = File::open("useragents.txt").unwrap();
+    BufReader::new(file)
+        .lines()
+        .map(|line| line.unwrap())
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split("    ").nth(5).map(|ua| ua.to_string()))
+        .collect()
+}
+
+fn parse_all(parser: &UserAgentParser, scratch: &mut Scratch, user_agents: &[String]) {
+    for user_agent in user_agents {
+        black_box(parser.parse_with(scratch, black_box(user_agent)));
+    }
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let parser = browscap_rs::load_parser_default().unwrap();
+    let user_agents = load_user_agents();
+
+    let mut group = c.benchmark_group("parse_useragents_txt");
+    group.bench_function("thread_local_scratch", |b| {
+        b.iter(|| {
+            for user_agent in user_agents.iter() {
+                black_box(parser.parse(black_box(user_agent)));
+            }
+        })
+    });
+    group.bench_function("reused_scratch", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&parser, &mut scratch, &user_agents))
+    });
+    // 每次解析都新建Scratch，相当于改造前每次parse都要分配的情况
+    group.bench_function("fresh_scratch", |b| {
+        b.iter(|| {
+            for user_agent in user_agents.iter() {
+                let mut scratch = Scratch::new();
+                black_box(parser.parse_with(&mut scratch, black_box(user_agent)));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_rule_index(c: &mut Criterion) {
+    let linear = UserAgentParser::builder().build().unwrap();
+    let indexed = UserAgentParser::builder().rule_index(true).build().unwrap();
+    let user_agents = load_user_agents();
+
+    let mut group = c.benchmark_group("rule_lookup");
+    group.bench_function("linear_scan", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&linear, &mut scratch, &user_agents))
+    });
+    group.bench_function("rule_index", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&indexed, &mut scratch, &user_agents))
+    });
+    group.finish();
+}
+
+fn bench_filter_masks(c: &mut Criterion) {
+    let dense = UserAgentParser::builder()
+        .compressed_masks(false)
+        .build()
+        .unwrap();
+    let compressed = UserAgentParser::builder()
+        .compressed_masks(true)
+        .build()
+        .unwrap();
+    let user_agents = load_user_agents();
+
+    let mut group = c.benchmark_group("filter_masks");
+    group.bench_function("dense", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&dense, &mut scratch, &user_agents))
+    });
+    group.bench_function("compressed", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&compressed, &mut scratch, &user_agents))
+    });
+    group.finish();
+}
+
+// RegexSet每次解析要几毫秒，只取一小部分UA比较
+fn bench_match_engine(c: &mut Criterion) {
+    let rules = UserAgentParser::builder().build().unwrap();
+    let regex_set = UserAgentParser::builder()
+        .engine(MatchEngine::RegexSet)
+        .build()
+        .unwrap();
+    let user_agents: Vec<String> = load_user_agents().into_iter().take(50).collect();
+
+    let mut group = c.benchmark_group("match_engine");
+    group.sample_size(10);
+    group.bench_function("rules", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&rules, &mut scratch, &user_agents))
+    });
+    group.bench_function("regex_set", |b| {
+        let mut scratch = Scratch::new();
+        b.iter(|| parse_all(&regex_set, &mut scratch, &user_agents))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_rule_index,
+    bench_filter_masks,
+    bench_match_engine
+);
+criterion_main!(benches);
@@ -0,0 +1,400 @@
+use crate::{BrowsCapField, LogFormat, LogSummary, ParseError, Scratch, UserAgentParser};
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read, Write};
+
+const COMBINED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i""#;
+const COMMON: &str = r#"%h %l %u %t "%r" %>s %b"#;
+
+//单行最多读取的字节数，超出的部分丢弃，使内存占用有上限
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LogToken {
+    Literal(String),
+    Field { user_agent: bool },
+}
+
+impl LogFormat {
+    /// The combined log format, the default of nginx and one of the standard formats of Apache.
+    pub fn combined() -> LogFormat {
+        LogFormat::parse(COMBINED).expect("the combined format is valid")
+    }
+
+    /// The common log format. It has no user agent, so every line is enriched with the
+    /// capabilities of an empty user agent.
+    pub fn common() -> LogFormat {
+        LogFormat::parse(COMMON).expect("the common format is valid")
+    }
+
+    /// Parses a format written like an nginx `log_format`, with variables such as `$status` and
+    /// `$http_user_agent`, or like an Apache `LogFormat`, with directives such as `%>s` and
+    /// `%{User-Agent}i`. `\"` stands for `"`, as in Apache configuration files.
+    ///
+    /// Fields must be separated by some text, otherwise it would be ambiguous where one ends.
+    pub fn parse(template: &str) -> Result<LogFormat, ParseError> {
+        let mut tokens: Vec<LogToken> = Vec::new();
+        let mut chars = template.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let field = match c {
+                '\\' if matches!(chars.peek(), Some((_, '"' | '\\'))) => {
+                    push_literal(&mut tokens, chars.next().unwrap().1);
+                    continue;
+                }
+                '%' if matches!(chars.peek(), Some((_, '%'))) => {
+                    push_literal(&mut tokens, chars.next().unwrap().1);
+                    continue;
+                }
+                '%' => {
+                    // 跳过修饰符，如%>s、%!200,304{Referer}i
+                    while chars
+                        .next_if(|(_, c)| "<>!,0123456789".contains(*c))
+                        .is_some()
+                    {}
+                    let mut name = String::new();
+                    if chars.next_if(|(_, c)| *c == '{').is_some() {
+                        loop {
+                            match chars.next() {
+                                Some((_, '}')) => break,
+                                Some((_, c)) => name.push(c),
+                                None => return Err(invalid_directive(template, start)),
+                            }
+                        }
+                    }
+                    match chars.next() {
+                        Some((_, kind)) if kind.is_ascii_alphabetic() => {
+                            kind == 'i' && name.eq_ignore_ascii_case("user-agent")
+                        }
+                        _ => return Err(invalid_directive(template, start)),
+                    }
+                }
+                '$' => {
+                    let braced = chars.next_if(|(_, c)| *c == '{').is_some();
+                    let mut name = String::new();
+                    while let Some((_, c)) =
+                        chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                    {
+                        name.push(c);
+                    }
+                    if braced && chars.next_if(|(_, c)| *c == '}').is_none() {
+                        return Err(invalid_directive(template, start));
+                    }
+                    if name.is_empty() {
+                        if braced {
+                            return Err(invalid_directive(template, start));
+                        }
+                        push_literal(&mut tokens, '$');
+                        continue;
+                    }
+                    name.eq_ignore_ascii_case("http_user_agent")
+                }
+                c => {
+                    push_literal(&mut tokens, c);
+                    continue;
+                }
+            };
+            if let Some(LogToken::Field { .. }) = tokens.last() {
+                return Err(ParseError::LogFormat(format!(
+                    "the field at {start} directly follows another field in `{template}`"
+                )));
+            }
+            tokens.push(LogToken::Field { user_agent: field });
+        }
+        Ok(LogFormat { my_tokens: tokens })
+    }
+
+    /// The user agent of an access log line, or `None` if the line does not match the format.
+    ///
+    /// Escape sequences written by nginx (`\x22`) and Apache (`\"`) are decoded, and the `-`
+    /// logged for a missing header gives an empty user agent, as does a format without a user
+    /// agent field. Text after the last field of the format is ignored, so lines with further
+    /// fields appended to a standard format still match it.
+    pub fn user_agent<'l>(&self, line: &'l str) -> Option<Cow<'l, str>> {
+        let mut rest = line;
+        let mut user_agent = None;
+        for (i, token) in self.my_tokens.iter().enumerate() {
+            match token {
+                LogToken::Literal(text) => rest = rest.strip_prefix(text.as_str())?,
+                LogToken::Field {
+                    user_agent: is_user_agent,
+                } => {
+                    let end = match self.my_tokens.get(i + 1) {
+                        // 引号中的字段可能含有后面的分隔符，找第一个没有转义的引号
+                        Some(LogToken::Literal(next))
+                            if next.starts_with('"') && is_quoted(&self.my_tokens, i) =>
+                        {
+                            quoted_end(rest)?
+                        }
+                        Some(LogToken::Literal(next)) => rest.find(next.as_str())?,
+                        _ => rest.len(),
+                    };
+                    let (value, tail) = rest.split_at(end);
+                    if *is_user_agent && user_agent.is_none() {
+                        user_agent = Some(value);
+                    }
+                    rest = tail;
+                }
+            }
+        }
+        match user_agent {
+            None | Some("-") => Some(Cow::Borrowed("")),
+            Some(user_agent) => Some(unescape(user_agent)),
+        }
+    }
+}
+
+impl UserAgentParser {
+    /// Reads access log lines from `input` and writes each of them to `output` as a JSON object
+    /// on its own line, with the original line as `line`, its user agent as `user_agent`, and
+    /// the value of each of `fields` under its lower-case name, `null` where there is none.
+    /// Lines that do not match `format` get a `null` user agent and no fields.
+    ///
+    /// One line is held in memory at a time. Invalid UTF-8 is replaced with U+FFFD, and lines
+    /// longer than 64 KiB are cut off there.
+    pub fn enrich_log(
+        &self,
+        format: &LogFormat,
+        fields: &[&'static BrowsCapField],
+        mut input: impl BufRead,
+        mut output: impl Write,
+    ) -> io::Result<LogSummary> {
+        let names: Vec<String> = fields
+            .iter()
+            .map(|field| field.name().to_ascii_lowercase())
+            .collect();
+        let mut summary = LogSummary::default();
+        let mut scratch = Scratch::new();
+        let mut buffer = Vec::new();
+        while read_line(&mut input, &mut buffer)? {
+            summary.lines += 1;
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(['\n', '\r']);
+            output.write_all(b"{\"line\":")?;
+            write_json_string(&mut output, line)?;
+            output.write_all(b",\"user_agent\":")?;
+            match format.user_agent(line) {
+                Some(user_agent) => {
+                    write_json_string(&mut output, &user_agent)?;
+                    let capabilities = self.parse_with(&mut scratch, &user_agent);
+                    for (field, name) in fields.iter().zip(&names) {
+                        output.write_all(b",")?;
+                        write_json_string(&mut output, name)?;
+                        output.write_all(b":")?;
+                        match capabilities.get_value(field) {
+                            Some(value) => write_json_string(&mut output, value)?,
+                            None => output.write_all(b"null")?,
+                        }
+                    }
+                }
+                None => {
+                    summary.unmatched += 1;
+                    output.write_all(b"null")?;
+                }
+            }
+            output.write_all(b"}\n")?;
+        }
+        output.flush()?;
+        Ok(summary)
+    }
+}
+
+fn push_literal(tokens: &mut Vec<LogToken>, c: char) {
+    match tokens.last_mut() {
+        Some(LogToken::Literal(text)) => text.push(c),
+        _ => tokens.push(LogToken::Literal(c.to_string())),
+    }
+}
+
+fn invalid_directive(template: &str, start: usize) -> ParseError {
+    ParseError::LogFormat(format!("incomplete field at {start} in `{template}`"))
+}
+
+//字段前面的文本是否以引号结尾
+fn is_quoted(tokens: &[LogToken], field: usize) -> bool {
+    field > 0 && matches!(&tokens[field - 1], LogToken::Literal(text) if text.ends_with('"'))
+}
+
+fn quoted_end(value: &str) -> Option<usize> {
+    let mut bytes = value.bytes().enumerate();
+    while let Some((i, byte)) = bytes.next() {
+        match byte {
+            b'\\' => {
+                bytes.next();
+            }
+            b'"' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+//解码nginx的\xHH和Apache的\"、\\转义
+fn unescape(value: &str) -> Cow<'_, str> {
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
+    }
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match rest {
+            [b'x', high, low, tail @ ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                let digit = |c: &u8| (*c as char).to_digit(16).unwrap() as u8;
+                bytes.push(digit(high) * 16 + digit(low));
+                rest = tail;
+            }
+            [c @ (b'"' | b'\\'), tail @ ..] => {
+                bytes.push(*c);
+                rest = tail;
+            }
+            _ => bytes.push(b'\\'),
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+//读取一行到buffer，超过MAX_LINE_LENGTH的部分跳过。没有更多行时返回false
+fn read_line(input: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<bool> {
+    buffer.clear();
+    let read = input
+        .by_ref()
+        .take(MAX_LINE_LENGTH as u64)
+        .read_until(b'\n', buffer)?;
+    if read == MAX_LINE_LENGTH && buffer.last() != Some(&b'\n') {
+        loop {
+            let available = input.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(i) => {
+                    input.consume(i + 1);
+                    break;
+                }
+                None => {
+                    let len = available.len();
+                    input.consume(len);
+                }
+            }
+        }
+    }
+    Ok(read > 0)
+}
+
+fn write_json_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod test_access_log {
+    use super::*;
+    use crate::{BROWSER, PLATFORM};
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+
+    fn combined_line(user_agent: &str) -> String {
+        format!(
+            r#"203.0.113.7 - - [10/Oct/2026:13:55:36 +0000] "GET /a?q=\"x\" HTTP/1.1" 200 2326 "https://example.com/" "{user_agent}""#
+        )
+    }
+
+    #[test]
+    fn test_user_agent() {
+        let combined = LogFormat::combined();
+        assert_eq!(
+            combined.user_agent(&combined_line(IPHONE)).as_deref(),
+            Some(IPHONE)
+        );
+        assert_eq!(
+            combined
+                .user_agent(&combined_line(r#"a \"b\" \x22c\x22 \\d \q"#))
+                .as_deref(),
+            Some(r#"a "b" "c" \d \q"#)
+        );
+        assert_eq!(
+            combined.user_agent(&combined_line("-")).as_deref(),
+            Some("")
+        );
+        let extended = format!("{} rt=0.012", combined_line(IPHONE));
+        assert_eq!(combined.user_agent(&extended).as_deref(), Some(IPHONE));
+        assert_eq!(combined.user_agent("not a log line"), None);
+
+        let common = LogFormat::common();
+        let line = r#"203.0.113.7 - frank [10/Oct/2026:13:55:36 +0000] "GET / HTTP/1.1" 200 -"#;
+        assert_eq!(common.user_agent(line).as_deref(), Some(""));
+
+        let nginx = LogFormat::parse(r#"$remote_addr "${http_user_agent}" $request_time"#).unwrap();
+        let line = format!(r#"::1 "{IPHONE}" 0.004"#);
+        assert_eq!(nginx.user_agent(&line).as_deref(), Some(IPHONE));
+        let apache = LogFormat::parse(r#"%h \"%{User-agent}i\" 100%% %D"#).unwrap();
+        let line = format!(r#"::1 "{IPHONE}" 100% 4000"#);
+        assert_eq!(apache.user_agent(&line).as_deref(), Some(IPHONE));
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        for template in ["%h%u", "$remote_addr$status", "%{Referer", "%", "${status"] {
+            let error = LogFormat::parse(template).unwrap_err();
+            assert!(matches!(error, ParseError::LogFormat(_)), "{template}");
+        }
+        assert!(LogFormat::parse("cost $ 5 $status").is_ok());
+    }
+
+    #[test]
+    fn test_enrich_log() {
+        let parser = UserAgentParser::builder().build().unwrap();
+        let long_line = "x".repeat(MAX_LINE_LENGTH + 10);
+        let input = format!(
+            "{}\r\ngarbage\n{long_line}\n{}",
+            combined_line(IPHONE),
+            combined_line("-")
+        );
+        let mut output = Vec::new();
+        let summary = parser
+            .enrich_log(
+                &LogFormat::combined(),
+                &[&BROWSER, &PLATFORM],
+                input.as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(
+            summary,
+            LogSummary {
+                lines: 4,
+                unmatched: 2
+            }
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let iphone = parser.parse(IPHONE);
+        let (browser, platform) = (iphone.get_browser().unwrap(), iphone.get_platform().unwrap());
+        assert!(lines[0].starts_with(r#"{"line":"203.0.113.7 - - [10/Oct/2026:13:55:36 +0000] \"GET /a?q=\\\"x\\\" HTTP/1.1\""#));
+        assert!(lines[0].ends_with(&format!(
+            r#""user_agent":"{IPHONE}","browser":"{browser}","platform":"{platform}"}}"#
+        )));
+        assert_eq!(lines[1], r#"{"line":"garbage","user_agent":null}"#);
+        assert_eq!(
+            lines[2].len(),
+            r#"{"line":"","user_agent":null}"#.len() + MAX_LINE_LENGTH
+        );
+        assert!(lines[3].ends_with(r#""user_agent":"","browser":"Unknown","platform":"Unknown"}"#));
+    }
+}
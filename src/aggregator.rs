@@ -0,0 +1,149 @@
+use crate::{AggregateEntry, Aggregator, BrowsCapField, Capabilities, FieldValue, IS_CRAWLER};
+
+//组合键中各字段值之间的分隔符，browscap的值中不会出现
+const SEPARATOR: char = '\u{1f}';
+
+impl Aggregator {
+    pub fn new() -> Aggregator {
+        Aggregator::default()
+    }
+
+    /// Adds a key to count by: one field, or several fields whose values are counted as one
+    /// combination, such as browser and major version. Values are those of
+    /// [`Capabilities::get_value`], so capabilities without a value are counted under the
+    /// placeholder for unknown values, and fields the parser did not load under an empty value.
+    pub fn count(mut self, fields: &[&'static BrowsCapField]) -> Aggregator {
+        self.my_keys.push(fields.to_vec());
+        self.my_counts.push(Default::default());
+        self
+    }
+
+    /// Leaves out capabilities whose `IS_CRAWLER` is `true`. The parser has to load
+    /// [`IS_CRAWLER`], otherwise nothing is left out.
+    pub fn exclude_crawlers(mut self, exclude: bool) -> Aggregator {
+        self.my_exclude_crawlers = exclude;
+        self
+    }
+
+    pub fn add(&mut self, capabilities: &Capabilities) {
+        if self.my_exclude_crawlers
+            && capabilities.get_field(&IS_CRAWLER) == FieldValue::Value("true")
+        {
+            self.my_crawlers += 1;
+            return;
+        }
+        self.my_total += 1;
+        let mut key = String::new();
+        for (fields, counts) in self.my_keys.iter().zip(&mut self.my_counts) {
+            key.clear();
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    key.push(SEPARATOR);
+                }
+                key.push_str(capabilities.get_value(field).unwrap_or_default());
+            }
+            // 已有的键不再分配内存
+            match counts.get_mut(key.as_str()) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(key.clone(), 1);
+                }
+            }
+        }
+    }
+
+    /// Capabilities counted, not including the crawlers left out.
+    pub fn total(&self) -> u64 {
+        self.my_total
+    }
+
+    /// Crawlers left out, see [`Aggregator::exclude_crawlers`].
+    pub fn crawlers(&self) -> u64 {
+        self.my_crawlers
+    }
+
+    /// The keys in the order they were added.
+    pub fn keys(&self) -> &[Vec<&'static BrowsCapField>] {
+        &self.my_keys
+    }
+
+    /// The `k` most frequent combinations of values of `key`, most frequent first and equally
+    /// frequent ones ordered by their values. Empty if `key` was not added with
+    /// [`Aggregator::count`].
+    pub fn top(&self, key: &[&'static BrowsCapField], k: usize) -> Vec<AggregateEntry<'_>> {
+        let Some(i) = self
+            .my_keys
+            .iter()
+            .position(|fields| fields.as_slice() == key)
+        else {
+            return Vec::new();
+        };
+        let mut counts: Vec<(&String, u64)> = self.my_counts[i]
+            .iter()
+            .map(|(values, count)| (values, *count))
+            .collect();
+        counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        counts
+            .into_iter()
+            .take(k)
+            .map(|(values, count)| AggregateEntry {
+                values: values.split(SEPARATOR).collect(),
+                count,
+                percentage: count as f64 * 100.0 / self.my_total as f64,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_aggregator {
+    use super::*;
+    use crate::capabilities::CapaCache;
+    use crate::{BROWSER, BROWSER_MAJOR_VERSION, PLATFORM, ParserOptions};
+
+    #[test]
+    fn test_aggregator() {
+        let mut cache = CapaCache::new(vec![&BROWSER, &BROWSER_MAJOR_VERSION, &IS_CRAWLER]);
+        let chrome_56 = cache.get_or_insert(&["Chrome", "56", "false"]);
+        let chrome_57 = cache.get_or_insert(&["Chrome", "57", "false"]);
+        let firefox = cache.get_or_insert(&["Firefox", "52", "false"]);
+        let bot = cache.get_or_insert(&["Googlebot", "2", "true"]);
+        let table = cache.finish(&ParserOptions::default());
+
+        let by_version: &[&'static BrowsCapField] = &[&BROWSER, &BROWSER_MAJOR_VERSION];
+        let mut aggregator = Aggregator::new()
+            .count(&[&BROWSER])
+            .count(by_version)
+            .count(&[&PLATFORM])
+            .exclude_crawlers(true);
+        for row in [chrome_56, firefox, chrome_57, bot, chrome_56, bot] {
+            aggregator.add(table.get(row));
+        }
+        assert_eq!(aggregator.total(), 4);
+        assert_eq!(aggregator.crawlers(), 2);
+        assert_eq!(aggregator.keys().len(), 3);
+
+        let top = aggregator.top(&[&BROWSER], 10);
+        let browsers: Vec<(&[&str], u64)> = top
+            .iter()
+            .map(|entry| (entry.values.as_slice(), entry.count))
+            .collect();
+        assert_eq!(browsers, vec![(&["Chrome"][..], 3), (&["Firefox"][..], 1)]);
+        assert_eq!(top[0].percentage, 75.0);
+
+        let top = aggregator.top(by_version, 2);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].values, vec!["Chrome", "56"]);
+        assert_eq!(top[1].values, vec!["Chrome", "57"]);
+        assert_eq!(top[1].percentage, 25.0);
+
+        // 未加载的字段计为空值
+        assert_eq!(aggregator.top(&[&PLATFORM], 10)[0].values, vec![""]);
+        assert!(aggregator.top(&[&IS_CRAWLER], 10).is_empty());
+
+        let mut all = Aggregator::new().count(&[&BROWSER]);
+        all.add(table.get(bot));
+        assert_eq!(all.crawlers(), 0);
+        assert_eq!(all.top(&[&BROWSER], 1)[0].values, vec!["Googlebot"]);
+    }
+}
@@ -0,0 +1,412 @@
+use browscap_rs::{
+    Aggregator, BROWSER, BROWSER_MAJOR_VERSION, BrowsCapField, DEVICE_TYPE, DatasetDiff,
+    IS_CRAWLER, LogFormat, PLATFORM, RuleOverlay, Scratch, UserAgentParser,
+};
+use clap::{Args, Parser, Subcommand};
+use output::{Format, RowWriter};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+mod output;
+
+/// Looks up browser, platform and device information for user agents with browscap.
+#[derive(Debug, Parser)]
+#[command(name = "browscap", version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    parse: ParseArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Writes access log lines as JSON lines with the browscap fields of their user agents
+    Log(LogArgs),
+    /// Counts the values of fields over user agents, one per line, and prints the most frequent
+    Report(ReportArgs),
+    /// Compares how two browscap files classify user agents, one per line, and prints the
+    /// changes
+    Diff(DiffArgs),
+}
+
+#[derive(Debug, Args)]
+struct DataArgs {
+    /// The browscap CSV file, or INI file in the php_browscap.ini format if its extension is
+    /// .ini; snapshot files are not supported. Without it the file is searched for at
+    /// $BROWSCAP_FILE, in the XDG data directories and in the current directory
+    #[arg(short, long, value_name = "FILE", value_parser = parse_data_file)]
+    data: Option<PathBuf>,
+    /// A CSV or TOML file of custom rules to add to the browscap ones, see
+    /// `RuleOverlay::from_file`
+    #[arg(long, value_name = "FILE")]
+    overlay: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct FieldArgs {
+    /// Comma-separated fields to print, such as browser,platform_version [default: the default
+    /// fields]
+    #[arg(short, long, value_delimiter = ',', value_parser = parse_field)]
+    fields: Vec<&'static BrowsCapField>,
+}
+
+#[derive(Debug, Args)]
+struct ParseArgs {
+    #[command(flatten)]
+    data: DataArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    #[arg(short = 'o', long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Also print the pattern of the matching rule
+    #[arg(short, long)]
+    explain: bool,
+    /// User agents to parse; read from stdin, one per line, when none are given
+    user_agents: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct LogArgs {
+    #[command(flatten)]
+    data: DataArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    /// `combined`, `common`, or a custom format written like an nginx log_format or an Apache
+    /// LogFormat, such as '$remote_addr "$http_user_agent"'
+    #[arg(short, long, default_value = "combined", value_parser = parse_log_format)]
+    log_format: LogFormat,
+    /// Log files to read; stdin when none are given
+    files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ReportArgs {
+    #[command(flatten)]
+    data: DataArgs,
+    /// A field to count by, or fields joined with + to count their combinations, such as
+    /// browser+browser_major_version. Can be repeated [default: browser,
+    /// browser+browser_major_version, platform, device_type]
+    #[arg(short, long, value_name = "FIELDS", value_parser = parse_key)]
+    by: Vec<Key>,
+    /// How many of the most frequent values to print for each key; the rest are summed up as
+    /// (other)
+    #[arg(short, long, default_value_t = 10)]
+    top: usize,
+    /// Leave out user agents of crawlers
+    #[arg(short, long)]
+    exclude_crawlers: bool,
+    #[arg(short = 'o', long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Files of user agents, one per line; stdin when none are given. Blank lines are skipped
+    files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    /// The browscap CSV file in use
+    #[arg(value_parser = parse_data_file)]
+    old: PathBuf,
+    /// The browscap CSV file to compare it with
+    #[arg(value_parser = parse_data_file)]
+    new: PathBuf,
+    #[command(flatten)]
+    fields: FieldArgs,
+    /// How many user agents to print as examples of each change
+    #[arg(short = 'n', long, default_value_t = 3)]
+    examples: usize,
+    #[arg(short = 'o', long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Files of user agents, one per line; stdin when none are given. Blank lines are skipped
+    files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct Key(Vec<&'static BrowsCapField>);
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Log(args)) => run_log(args),
+        Some(Command::Report(args)) => run_report(args),
+        Some(Command::Diff(args)) => run_diff(args),
+        None => run_parse(cli.parse),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // 输出被关闭（如管道到head）时正常退出
+        Err(error)
+            if error
+                .downcast_ref::<io::Error>()
+                .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("browscap: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_field(name: &str) -> Result<&'static BrowsCapField, String> {
+    BrowsCapField::from_name(name.trim()).ok_or_else(|| format!("unknown field `{name}`"))
+}
+
+//只能加载CSV和INI，其他扩展名（如快照文件）直接报错，不当作CSV解析
+fn parse_data_file(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    match path.extension() {
+        Some(extension)
+            if !extension.eq_ignore_ascii_case("csv") && !extension.eq_ignore_ascii_case("ini") =>
+        {
+            Err(format!(
+                "only browscap CSV and INI files can be loaded, not `{}`",
+                path.display()
+            ))
+        }
+        _ => Ok(path),
+    }
+}
+
+fn parse_key(key: &str) -> Result<Key, String> {
+    key.split('+')
+        .map(parse_field)
+        .collect::<Result<_, _>>()
+        .map(Key)
+}
+
+fn parse_log_format(format: &str) -> Result<LogFormat, String> {
+    match format {
+        "combined" => Ok(LogFormat::combined()),
+        "common" => Ok(LogFormat::common()),
+        format => LogFormat::parse(format).map_err(|error| error.to_string()),
+    }
+}
+
+impl DataArgs {
+    fn load(&self, fields: Vec<&'static BrowsCapField>) -> Result<UserAgentParser, Box<dyn Error>> {
+        let mut builder = UserAgentParser::builder().fields(fields);
+        if let Some(data) = &self.data {
+            builder = builder.file(data);
+        }
+        if let Some(overlay) = &self.overlay {
+            builder = builder.overlay(RuleOverlay::from_file(overlay)?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+impl FieldArgs {
+    //要输出的字段，没有指定时为默认字段
+    fn output_fields(&self) -> Vec<&'static BrowsCapField> {
+        if self.fields.is_empty() {
+            BrowsCapField::values()
+                .iter()
+                .filter(|field| field.is_default())
+                .collect()
+        } else {
+            self.fields.clone()
+        }
+    }
+}
+
+fn column_name(field: &BrowsCapField) -> String {
+    field.name().to_ascii_lowercase()
+}
+
+fn run_parse(args: ParseArgs) -> Result<(), Box<dyn Error>> {
+    let parser = args.data.load(args.fields.fields.clone())?;
+    let fields = args.fields.output_fields();
+    let mut columns = vec!["user_agent".to_string()];
+    columns.extend(fields.iter().map(|field| column_name(field)));
+    if args.explain {
+        columns.push("pattern".to_string());
+    }
+    let out = BufWriter::new(io::stdout().lock());
+    let mut writer = RowWriter::new(args.format, out, columns)?;
+    let mut write = |user_agent: &str| {
+        let capabilities = parser.parse(user_agent);
+        let mut row = vec![Some(user_agent.to_string())];
+        row.extend(
+            fields
+                .iter()
+                .map(|field| capabilities.get_value(field).map(str::to_string)),
+        );
+        if args.explain {
+            row.push(parser.matched_pattern(user_agent));
+        }
+        writer.write_row(row)
+    };
+    if args.user_agents.is_empty() {
+        for_each_line(io::stdin().lock(), |line| write(line))?;
+    } else {
+        for user_agent in &args.user_agents {
+            write(user_agent)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn run_log(args: LogArgs) -> Result<(), Box<dyn Error>> {
+    let parser = args.data.load(args.fields.fields.clone())?;
+    let fields = args.fields.output_fields();
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut lines = 0;
+    let mut unmatched = 0;
+    for_each_input(&args.files, |input| {
+        let summary = parser.enrich_log(&args.log_format, &fields, input, &mut out)?;
+        lines += summary.lines;
+        unmatched += summary.unmatched;
+        Ok(())
+    })?;
+    if unmatched > 0 {
+        eprintln!("browscap: {unmatched} of {lines} lines did not match the log format");
+    }
+    Ok(())
+}
+
+fn run_report(args: ReportArgs) -> Result<(), Box<dyn Error>> {
+    let keys: Vec<Vec<&'static BrowsCapField>> = if args.by.is_empty() {
+        vec![
+            vec![&BROWSER],
+            vec![&BROWSER, &BROWSER_MAJOR_VERSION],
+            vec![&PLATFORM],
+            vec![&DEVICE_TYPE],
+        ]
+    } else {
+        args.by.into_iter().map(|key| key.0).collect()
+    };
+    let mut fields: Vec<&'static BrowsCapField> = keys.iter().flatten().copied().collect();
+    if args.exclude_crawlers {
+        fields.push(&IS_CRAWLER);
+    }
+    let parser = args.data.load(fields)?;
+    let mut aggregator = keys
+        .iter()
+        .fold(Aggregator::new(), |aggregator, key| aggregator.count(key))
+        .exclude_crawlers(args.exclude_crawlers);
+    let mut scratch = Scratch::new();
+    for_each_input(&args.files, |input| {
+        for_each_line(input, |line| {
+            if !line.trim().is_empty() {
+                aggregator.add(parser.parse_with(&mut scratch, line));
+            }
+            Ok(())
+        })
+    })?;
+
+    let columns = ["key", "value", "count", "percent"]
+        .map(String::from)
+        .to_vec();
+    let out = BufWriter::new(io::stdout().lock());
+    let mut writer = RowWriter::new(args.format, out, columns)?;
+    let percent = |count: u64| format!("{:.1}", count as f64 * 100.0 / aggregator.total() as f64);
+    for key in aggregator.keys() {
+        let name: Vec<String> = key.iter().map(|field| column_name(field)).collect();
+        let name = name.join("+");
+        let top = aggregator.top(key, args.top);
+        for entry in &top {
+            writer.write_row(vec![
+                Some(name.clone()),
+                Some(entry.values.join(" ")),
+                Some(entry.count.to_string()),
+                Some(format!("{:.1}", entry.percentage)),
+            ])?;
+        }
+        let other = aggregator.total() - top.iter().map(|entry| entry.count).sum::<u64>();
+        if other > 0 {
+            writer.write_row(vec![
+                Some(name),
+                Some("(other)".to_string()),
+                Some(other.to_string()),
+                Some(percent(other)),
+            ])?;
+        }
+    }
+    writer.finish()?;
+    if aggregator.crawlers() > 0 {
+        eprintln!("browscap: left out {} crawlers", aggregator.crawlers());
+    }
+    Ok(())
+}
+
+fn run_diff(args: DiffArgs) -> Result<(), Box<dyn Error>> {
+    let load = |path: &PathBuf| {
+        UserAgentParser::builder()
+            .fields(args.fields.fields.clone())
+            .file(path)
+            .build()
+    };
+    let old = load(&args.old)?;
+    let new = load(&args.new)?;
+    let mut diff = DatasetDiff::new(&args.fields.output_fields()).max_examples(args.examples);
+    for_each_input(&args.files, |input| {
+        for_each_line(input, |line| {
+            if !line.trim().is_empty() {
+                diff.add(&old, &new, line);
+            }
+            Ok(())
+        })
+    })?;
+
+    let columns = ["field", "old", "new", "count", "percent", "examples"];
+    let out = BufWriter::new(io::stdout().lock());
+    let mut writer = RowWriter::new(args.format, out, columns.map(String::from).to_vec())?;
+    for change in diff.changes() {
+        writer.write_row(vec![
+            Some(column_name(change.field)),
+            Some(change.old.to_string()),
+            Some(change.new.to_string()),
+            Some(change.count.to_string()),
+            Some(format!(
+                "{:.1}",
+                change.count as f64 * 100.0 / diff.total() as f64
+            )),
+            Some(change.examples.join(" | ")),
+        ])?;
+    }
+    writer.finish()?;
+    eprintln!(
+        "browscap: {} of {} user agents changed",
+        diff.changed(),
+        diff.total()
+    );
+    Ok(())
+}
+
+//依次读取每个文件，没有给出文件时读取stdin
+fn for_each_input(
+    files: &[PathBuf],
+    mut f: impl FnMut(&mut dyn BufRead) -> io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    if files.is_empty() {
+        f(&mut io::stdin().lock())?;
+    }
+    for path in files {
+        let file =
+            File::open(path).map_err(|error| format!("cannot read {}: {error}", path.display()))?;
+        f(&mut BufReader::new(file))?;
+    }
+    Ok(())
+}
+
+//逐行读取，不是UTF-8的字节替换成U+FFFD，只占用一行的内存
+fn for_each_line(
+    mut input: impl BufRead,
+    mut f: impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        if input.read_until(b'\n', &mut buffer)? == 0 {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        f(line.trim_end_matches(['\n', '\r']))?;
+    }
+}
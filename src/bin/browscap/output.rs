@@ -0,0 +1,215 @@
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Aligned columns, printed once all input is read
+    Table,
+    Csv,
+    Tsv,
+    /// One JSON object per line
+    Json,
+}
+
+/*
+    按选定的格式输出表格数据。除table外每行立即写出，table需要先缓存所有行来计算列宽。
+    值为None时文本格式输出空串，JSON输出null
+*/
+pub struct RowWriter<W: Write> {
+    my_format: Format,
+    my_out: W,
+    my_columns: Vec<String>,
+    my_rows: Vec<Vec<Option<String>>>,
+}
+
+impl<W: Write> RowWriter<W> {
+    pub fn new(format: Format, mut out: W, columns: Vec<String>) -> io::Result<RowWriter<W>> {
+        match format {
+            Format::Csv => {
+                write_csv_row(&mut out, columns.iter().map(|column| Some(column.as_str())))?
+            }
+            Format::Tsv => {
+                write_tsv_row(&mut out, columns.iter().map(|column| Some(column.as_str())))?
+            }
+            Format::Table | Format::Json => {}
+        }
+        Ok(RowWriter {
+            my_format: format,
+            my_out: out,
+            my_columns: columns,
+            my_rows: Vec::new(),
+        })
+    }
+
+    pub fn write_row(&mut self, row: Vec<Option<String>>) -> io::Result<()> {
+        let values = row.iter().map(|value| value.as_deref());
+        match self.my_format {
+            Format::Table => {
+                self.my_rows.push(row);
+                Ok(())
+            }
+            Format::Csv => write_csv_row(&mut self.my_out, values),
+            Format::Tsv => write_tsv_row(&mut self.my_out, values),
+            Format::Json => write_json_row(&mut self.my_out, &self.my_columns, values),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.my_format == Format::Table {
+            write_table(&mut self.my_out, &self.my_columns, &self.my_rows)?;
+        }
+        self.my_out.flush()
+    }
+}
+
+fn write_csv_row<'v>(
+    out: &mut impl Write,
+    values: impl Iterator<Item = Option<&'v str>>,
+) -> io::Result<()> {
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        let value = value.unwrap_or_default();
+        if value.contains(['"', ',', '\n', '\r']) {
+            write!(out, "\"{}\"", value.replace('"', "\"\""))?;
+        } else {
+            out.write_all(value.as_bytes())?;
+        }
+    }
+    out.write_all(b"\n")
+}
+
+//TSV没有转义，值中的制表符和换行替换成空格
+fn write_tsv_row<'v>(
+    out: &mut impl Write,
+    values: impl Iterator<Item = Option<&'v str>>,
+) -> io::Result<()> {
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            out.write_all(b"\t")?;
+        }
+        let value = value.unwrap_or_default();
+        out.write_all(value.replace(['\t', '\n', '\r'], " ").as_bytes())?;
+    }
+    out.write_all(b"\n")
+}
+
+fn write_json_row<'v>(
+    out: &mut impl Write,
+    columns: &[String],
+    values: impl Iterator<Item = Option<&'v str>>,
+) -> io::Result<()> {
+    out.write_all(b"{")?;
+    for (i, (column, value)) in columns.iter().zip(values).enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write_json_string(out, column)?;
+        out.write_all(b":")?;
+        match value {
+            Some(value) => write_json_string(out, value)?,
+            None => out.write_all(b"null")?,
+        }
+    }
+    out.write_all(b"}\n")
+}
+
+pub fn write_json_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{c}")?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn write_table(
+    out: &mut impl Write,
+    columns: &[String],
+    rows: &[Vec<Option<String>>],
+) -> io::Result<()> {
+    let mut widths: Vec<usize> = columns
+        .iter()
+        .map(|column| column.chars().count())
+        .collect();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.as_deref().unwrap_or_default().chars().count());
+        }
+    }
+    let header = columns.iter().map(|column| Some(column.as_str()));
+    write_table_row(out, &widths, header)?;
+    for row in rows {
+        write_table_row(out, &widths, row.iter().map(|value| value.as_deref()))?;
+    }
+    Ok(())
+}
+
+fn write_table_row<'v>(
+    out: &mut impl Write,
+    widths: &[usize],
+    values: impl Iterator<Item = Option<&'v str>>,
+) -> io::Result<()> {
+    let mut line = String::new();
+    for (i, (width, value)) in widths.iter().zip(values).enumerate() {
+        if i > 0 {
+            line.push_str("  ");
+        }
+        let value = value.unwrap_or_default().replace(['\t', '\n', '\r'], " ");
+        line.push_str(&value);
+        line.extend(std::iter::repeat_n(' ', width - value.chars().count()));
+    }
+    writeln!(out, "{}", line.trim_end())
+}
+
+#[cfg(test)]
+mod test_output {
+    use super::*;
+
+    fn render(format: Format, rows: Vec<Vec<Option<String>>>) -> String {
+        let columns = vec!["user_agent".to_string(), "browser".to_string()];
+        let mut out = Vec::new();
+        let mut writer = RowWriter::new(format, &mut out, columns).unwrap();
+        for row in rows {
+            writer.write_row(row).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn rows() -> Vec<Vec<Option<String>>> {
+        vec![
+            vec![Some("a, \"b\"".into()), Some("Chrome".into())],
+            vec![Some("tab\there".into()), None],
+        ]
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(
+            render(Format::Csv, rows()),
+            "user_agent,browser\n\"a, \"\"b\"\"\",Chrome\ntab\there,\n"
+        );
+        assert_eq!(
+            render(Format::Tsv, rows()),
+            "user_agent\tbrowser\na, \"b\"\tChrome\ntab here\t\n"
+        );
+        assert_eq!(
+            render(Format::Json, rows()),
+            "{\"user_agent\":\"a, \\\"b\\\"\",\"browser\":\"Chrome\"}\n\
+             {\"user_agent\":\"tab\\there\",\"browser\":null}\n"
+        );
+        assert_eq!(
+            render(Format::Table, rows()),
+            "user_agent  browser\na, \"b\"      Chrome\ntab here\n"
+        );
+    }
+}
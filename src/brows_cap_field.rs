@@ -39,6 +39,16 @@ impl BrowsCapField {
     pub fn name(&self) -> &'static str {
         self.name
     }
+
+    //browscap文件中该字段的列名，也是INI文件中的键
+    pub(crate) fn column(&self) -> &'static str {
+        COLUMNS[self.index()]
+    }
+
+    /// The field called `name`, ignoring ASCII case.
+    pub fn from_name(name: &str) -> Option<&'static BrowsCapField> {
+        VALUES.iter().find(|field| field.name.eq_ignore_ascii_case(name))
+    }
 }
 
 // VALUES 数组
@@ -94,3 +104,58 @@ pub const VALUES: [BrowsCapField; 50] = [
     RENDERING_ENGINE_DESCRIPTION,
     RENDERING_ENGINE_MAKER,
 ];
+
+// browscap CSV文件的表头，按index排列，第0列是pattern
+const COLUMNS: [&str; 51] = [
+    "PropertyName",
+    "MasterParent",
+    "LiteMode",
+    "Parent",
+    "Comment",
+    "Browser",
+    "Browser_Type",
+    "Browser_Bits",
+    "Browser_Maker",
+    "Browser_Modus",
+    "Version",
+    "MajorVer",
+    "MinorVer",
+    "Platform",
+    "Platform_Version",
+    "Platform_Description",
+    "Platform_Bits",
+    "Platform_Maker",
+    "Alpha",
+    "Beta",
+    "Win16",
+    "Win32",
+    "Win64",
+    "Frames",
+    "IFrames",
+    "Tables",
+    "Cookies",
+    "BackgroundSounds",
+    "JavaScript",
+    "VBScript",
+    "JavaApplets",
+    "ActiveXControls",
+    "isMobileDevice",
+    "isTablet",
+    "isSyndicationReader",
+    "Crawler",
+    "isFake",
+    "isAnonymized",
+    "isModified",
+    "CssVersion",
+    "AolVersion",
+    "Device_Name",
+    "Device_Maker",
+    "Device_Type",
+    "Device_Pointing_Method",
+    "Device_Code_Name",
+    "Device_Brand_Name",
+    "RenderingEngine_Name",
+    "RenderingEngine_Version",
+    "RenderingEngine_Description",
+    "RenderingEngine_Maker",
+];
@@ -1,47 +1,398 @@
-use crate::mapper::{MAPPER};
 use crate::{
     BROWSER, BROWSER_MAJOR_VERSION, BROWSER_TYPE, BrowsCapField, Capabilities, DEVICE_TYPE,
-    PLATFORM, PLATFORM_VERSION, capabilities,
+    Fallback, FieldValue, PLATFORM, PLATFORM_VERSION, ParserOptions,
 };
-use hashbrown::HashSet;
-use std::cell::{RefCell};
+use hashbrown::HashMap;
+use std::fmt::{Debug, Formatter};
 use std::hash::Hash;
-use std::sync::{Arc, OnceLock};
-use ustr::Ustr;
+use std::sync::Arc;
 
-pub const UNKNOWN_BROWSCAP_VALUE: &'static str = "Unknown";
+pub const UNKNOWN_BROWSCAP_VALUE: &str = "Unknown";
+//没有值的字段在字典中的值，数据中的值都去掉了首尾空白且不为空，不会与它混淆
+pub(crate) const MISSING_VALUE: &str = "";
+const DEFAULT_BROWSER_VALUE: &str = "Default Browser";
 
-pub static DEFAULT_CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+//自定义的fallback或overlay rule的capabilities，没有给出的字段没有值
+pub(crate) type CustomValues = Vec<(&'static BrowsCapField, Box<str>)>;
 
+/*
+    一个parser的所有Capabilities共用的字段布局和值字典。
+    每个字段的字典保存该字段所有不同的值，Capabilities只保存值在字典中的编号，
+    编号按字典大小所需的最少位数依次紧凑地存放在一行u64里
+*/
+#[derive(Debug)]
+pub(crate) struct CapabilitySchema {
+    my_fields: Vec<&'static BrowsCapField>,
+    //按ordinal索引，字段在行中的位置
+    my_slots: Vec<Option<u32>>,
+    my_dictionaries: Vec<Box<[Box<str>]>>,
+    my_offsets: Vec<u32>,
+    my_widths: Vec<u32>,
+    //没有值的字段对外显示的值
+    my_unknown_value: Box<str>,
+}
+
+impl CapabilitySchema {
+    //字典中原样保存的值，没有值时为MISSING_VALUE
+    fn get_raw(&self, row: &[u64], slot: usize) -> &str {
+        let code = read_bits(row, self.my_offsets[slot], self.my_widths[slot]);
+        &self.my_dictionaries[slot][code as usize]
+    }
+
+    fn get_value(&self, row: &[u64], slot: usize) -> &str {
+        match self.get_raw(row, slot) {
+            MISSING_VALUE => &self.my_unknown_value,
+            value => value,
+        }
+    }
+}
+
+/*
+    加载时使用：为每个字段建立值字典，并给相同的编号行去重。
+    加载完成后由finish按最终的字典大小压缩成CapabilityTable
+*/
 #[derive(Debug)]
 pub struct CapaCache {
-    cache: RefCell<HashSet<Arc<Capabilities>>>,
+    my_fields: Vec<&'static BrowsCapField>,
+    my_dictionaries: Vec<HashMap<Box<str>, u32>>,
+    my_rows: HashMap<Box<[u32]>, u32>,
+}
+
+/// Every distinct capability set of a parser, indexed by the rules.
+#[derive(Debug, Clone)]
+pub struct CapabilityTable {
+    //加载时生成的行，复制parser时共享
+    my_rows: Arc<[Capabilities]>,
+    //加载完成后追加的行及其值，共用一个只含这些行的值的字段布局
+    my_custom_rows: Vec<Capabilities>,
+    my_custom_values: Vec<CustomValues>,
+    //按Fallback索引
+    my_fallbacks: [u32; 3],
 }
 
 impl CapaCache {
-    pub fn new() -> CapaCache {
+    pub fn new(fields: Vec<&'static BrowsCapField>) -> CapaCache {
         CapaCache {
-            cache: RefCell::new(HashSet::new()),
+            my_dictionaries: vec![HashMap::new(); fields.len()],
+            my_fields: fields,
+            my_rows: HashMap::new(),
         }
     }
 
-    pub fn get_or_insert(&self, capa: Capabilities) -> Arc<Capabilities> {
-        self.cache.borrow_mut().get_or_insert(Arc::new(capa)).clone()
+    //values与字段一一对应，返回去重后的行号
+    pub fn get_or_insert(&mut self, values: &[&str]) -> u32 {
+        let codes: Box<[u32]> = values
+            .iter()
+            .zip(self.my_dictionaries.iter_mut())
+            .map(|(value, dictionary)| match dictionary.get(*value) {
+                Some(code) => *code,
+                None => {
+                    let code = dictionary.len() as u32;
+                    dictionary.insert((*value).into(), code);
+                    code
+                }
+            })
+            .collect();
+        let next = self.my_rows.len() as u32;
+        *self.my_rows.entry(codes).or_insert(next)
+    }
+
+    //没有匹配任何rule时默认的capabilities：浏览器为Default Browser，其余都没有值
+    fn get_wild_card(&mut self) -> u32 {
+        let values: Vec<&str> = self
+            .my_fields
+            .iter()
+            .map(|field| {
+                if **field == BROWSER || **field == BROWSER_TYPE {
+                    DEFAULT_BROWSER_VALUE
+                } else {
+                    MISSING_VALUE
+                }
+            })
+            .collect();
+        self.get_or_insert(&values)
+    }
+
+    //给出的字段取给出的值，其余字段没有值；未加载的字段被忽略，同一字段给出多次时取最后一个
+    pub fn get_custom(&mut self, values: &[(&'static BrowsCapField, Box<str>)]) -> u32 {
+        let row: Vec<&str> = self
+            .my_fields
+            .iter()
+            .map(|field| {
+                values
+                    .iter()
+                    .rev()
+                    .find(|(custom, _)| *custom == *field)
+                    .map_or(MISSING_VALUE, |(_, value)| value.trim())
+            })
+            .collect();
+        self.get_or_insert(&row)
+    }
+
+    pub fn finish(mut self, options: &ParserOptions) -> CapabilityTable {
+        let mut my_fallbacks = [0; 3];
+        for fallback in Fallback::ALL {
+            my_fallbacks[fallback as usize] = match (&options.fallbacks[fallback as usize], fallback) {
+                (Some(values), _) => self.get_custom(values),
+                (None, Fallback::Unmatched) => self.get_wild_card(),
+                (None, Fallback::Empty | Fallback::TooLong) => {
+                    let missing = vec![MISSING_VALUE; self.my_fields.len()];
+                    self.get_or_insert(&missing)
+                }
+            };
+        }
+
+        let mut my_slots = vec![None; BrowsCapField::values().len()];
+        for (slot, field) in self.my_fields.iter().enumerate() {
+            my_slots[field.ordinal()] = Some(slot as u32);
+        }
+        let mut my_offsets = Vec::with_capacity(self.my_fields.len());
+        let mut my_widths = Vec::with_capacity(self.my_fields.len());
+        let mut row_bits = 0;
+        let my_dictionaries: Vec<Box<[Box<str>]>> = self
+            .my_dictionaries
+            .into_iter()
+            .map(|dictionary| {
+                let width = bits_needed(dictionary.len());
+                my_offsets.push(row_bits);
+                my_widths.push(width);
+                row_bits += width;
+                let mut values: Vec<(Box<str>, u32)> = dictionary.into_iter().collect();
+                values.sort_by_key(|(_, code)| *code);
+                values.into_iter().map(|(value, _)| value).collect()
+            })
+            .collect();
+        let schema = Arc::new(CapabilitySchema {
+            my_fields: self.my_fields,
+            my_slots,
+            my_dictionaries,
+            my_offsets,
+            my_widths,
+            my_unknown_value: options.unknown_value.clone(),
+        });
+
+        let mut rows: Vec<(Box<[u32]>, u32)> = self.my_rows.into_iter().collect();
+        rows.sort_by_key(|(_, index)| *index);
+        let row_words = row_bits.div_ceil(u64::BITS) as usize;
+        let my_rows = rows
+            .into_iter()
+            .map(|(codes, _)| {
+                let mut row = vec![0u64; row_words].into_boxed_slice();
+                for (slot, code) in codes.iter().enumerate() {
+                    write_bits(&mut row, schema.my_offsets[slot], *code);
+                }
+                Capabilities {
+                    my_schema: schema.clone(),
+                    my_row: row,
+                }
+            })
+            .collect();
+        CapabilityTable {
+            my_rows,
+            my_custom_rows: Vec::new(),
+            my_custom_values: Vec::new(),
+            my_fallbacks,
+        }
     }
 }
 
-impl Capabilities {
-    fn new(values: Vec<Ustr>) -> Capabilities {
-        Capabilities { my_values: values }
+impl CapabilityTable {
+    pub fn get(&self, index: u32) -> &Capabilities {
+        let index = index as usize;
+        match self.my_rows.get(index) {
+            Some(row) => row,
+            None => &self.my_custom_rows[index - self.my_rows.len()],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.my_rows.len() + self.my_custom_rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //所有行和共用的字段布局、字典占用的堆内存，不含字典中字符串的内容
+    pub(crate) fn memory_usage(&self) -> usize {
+        let rows = self
+            .my_rows
+            .iter()
+            .chain(self.my_custom_rows.iter())
+            .map(|row| size_of_val(&*row.my_row))
+            .sum::<usize>();
+        let schemas = self
+            .schemas()
+            .map(|schema| {
+                size_of::<CapabilitySchema>()
+                    + 2 * size_of::<usize>()
+                    + schema.my_fields.capacity() * size_of::<&BrowsCapField>()
+                    + schema.my_slots.capacity() * size_of::<Option<u32>>()
+                    + (schema.my_offsets.capacity() + schema.my_widths.capacity())
+                        * size_of::<u32>()
+                    + schema
+                        .my_dictionaries
+                        .iter()
+                        .map(|dictionary| {
+                            size_of_val(&**dictionary) + size_of::<Box<[Box<str>]>>()
+                        })
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+        let custom_values = self
+            .my_custom_values
+            .iter()
+            .map(|values| values.capacity() * size_of::<(&BrowsCapField, Box<str>)>())
+            .sum::<usize>();
+        (self.my_rows.len() + self.my_custom_rows.capacity()) * size_of::<Capabilities>()
+            + self.my_custom_values.capacity() * size_of::<CustomValues>()
+            + rows
+            + schemas
+            + custom_values
+    }
+
+    //字典中字符串内容占用的字节数
+    pub(crate) fn string_bytes(&self) -> usize {
+        self.schemas()
+            .map(|schema| {
+                schema
+                    .my_dictionaries
+                    .iter()
+                    .flat_map(|dictionary| dictionary.iter())
+                    .map(|value| value.len())
+                    .sum::<usize>()
+                    + schema.my_unknown_value.len()
+            })
+            .sum()
+    }
+
+    //加载时的字段布局，以及追加的行共用的字段布局
+    fn schemas(&self) -> impl Iterator<Item = &CapabilitySchema> {
+        self.my_rows
+            .first()
+            .into_iter()
+            .chain(self.my_custom_rows.first())
+            .map(|row| &*row.my_schema)
+    }
+
+    pub fn get_fallback(&self, fallback: Fallback) -> &Capabilities {
+        self.get(self.my_fallbacks[fallback as usize])
+    }
+
+    /*
+        加载完成后追加一行，返回行号。加载时的行和字典不变，仍与其他parser共享；
+        追加的行共用一个字段布局，只按追加的行重新生成，开销与追加的行数有关，与rule数无关
+    */
+    pub(crate) fn push_custom(&mut self, values: &[(&'static BrowsCapField, Box<str>)]) -> u32 {
+        self.my_custom_values.push(values.to_vec());
+        // finish至少生成了fallback的行
+        let schema = &self.my_rows[0].my_schema;
+        let mut cache = CapaCache::new(schema.my_fields.clone());
+        let rows: Vec<u32> = self
+            .my_custom_values
+            .iter()
+            .map(|values| cache.get_custom(values))
+            .collect();
+        let options = ParserOptions {
+            unknown_value: schema.my_unknown_value.clone(),
+            ..ParserOptions::default()
+        };
+        let table = cache.finish(&options);
+        self.my_custom_rows = rows.into_iter().map(|row| table.get(row).clone()).collect();
+        (self.len() - 1) as u32
+    }
+}
+
+//只有一行的Capabilities，字段布局只含这一行的值，insert向空的CapaCache登记这一行
+pub(crate) fn single_row(
+    fields: Vec<&'static BrowsCapField>,
+    unknown_value: &str,
+    insert: impl FnOnce(&mut CapaCache) -> u32,
+) -> Capabilities {
+    let mut cache = CapaCache::new(fields);
+    let row = insert(&mut cache);
+    let options = ParserOptions {
+        unknown_value: unknown_value.into(),
+        ..ParserOptions::default()
+    };
+    cache.finish(&options).get(row).clone()
+}
+
+//字典有len个值时编号需要的位数
+fn bits_needed(len: usize) -> u32 {
+    if len <= 1 {
+        0
+    } else {
+        usize::BITS - (len - 1).leading_zeros()
     }
+}
+
+fn read_bits(row: &[u64], offset: u32, width: u32) -> u32 {
+    if width == 0 {
+        return 0;
+    }
+    let word = (offset / u64::BITS) as usize;
+    let shift = offset % u64::BITS;
+    let mut bits = row[word] >> shift;
+    if shift + width > u64::BITS {
+        bits |= row[word + 1] << (u64::BITS - shift);
+    }
+    (bits & ((1u64 << width) - 1)) as u32
+}
+
+fn write_bits(row: &mut [u64], offset: u32, code: u32) {
+    if code == 0 {
+        return;
+    }
+    let word = (offset / u64::BITS) as usize;
+    let shift = offset % u64::BITS;
+    row[word] |= (code as u64) << shift;
+    if shift > 0 && (code as u64) >> (u64::BITS - shift) != 0 {
+        row[word + 1] |= (code as u64) >> (u64::BITS - shift);
+    }
+}
+
+impl Capabilities {
+    /// The value of `field`, or `None` when it was not loaded. Fields without a value in the
+    /// browscap data read as the placeholder set with [`crate::ParserBuilder::unknown_value`];
+    /// use [`Capabilities::get_field`] to tell them apart from real values.
     pub fn get_value(&self, field: &BrowsCapField) -> Option<&str> {
-        let u_str = MAPPER
-            .get()
-            .unwrap()
-            .read()
-            .unwrap()
-            .get_value(&self.my_values, field);
-        return u_str.map(|x| x.as_str());
+        let slot = self.get_slot(field)?;
+        Some(self.my_schema.get_value(&self.my_row, slot))
+    }
+
+    pub fn get_field(&self, field: &BrowsCapField) -> FieldValue<'_> {
+        let Some(slot) = self.get_slot(field) else {
+            return FieldValue::NotLoaded;
+        };
+        match self.my_schema.get_raw(&self.my_row, slot) {
+            MISSING_VALUE => FieldValue::Unknown,
+            value => FieldValue::Value(value),
+        }
+    }
+
+    fn get_slot(&self, field: &BrowsCapField) -> Option<usize> {
+        let slot = (*self.my_schema.my_slots.get(field.ordinal())?)?;
+        Some(slot as usize)
+    }
+
+    /// Every loaded field with its value as returned by [`Capabilities::get_value`], in ordinal
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static BrowsCapField, &str)> {
+        self.my_schema
+            .my_fields
+            .iter()
+            .enumerate()
+            .map(|(slot, field)| (*field, self.my_schema.get_value(&self.my_row, slot)))
+    }
+
+    //字典中的原始值，不受占位符影响
+    fn raw_values(&self) -> impl Iterator<Item = (&'static BrowsCapField, &str)> {
+        self.my_schema
+            .my_fields
+            .iter()
+            .enumerate()
+            .map(|(slot, field)| (*field, self.my_schema.get_raw(&self.my_row, slot)))
     }
 
     pub fn get_browser(&self) -> Option<&str> {
@@ -69,9 +420,19 @@ impl Capabilities {
     }
 }
 
+impl Debug for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Capabilities ")?;
+        f.debug_map()
+            .entries(self.iter().map(|(field, value)| (field.name(), value)))
+            .finish()
+    }
+}
+
+// 按字符串内容计算hash，不依赖编号、字典和占位符，不同parser、不同进程间结果一致
 impl Hash for Capabilities {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        for value in self.my_values.iter() {
+        for (_, value) in self.raw_values() {
             value.hash(state);
         }
     }
@@ -80,42 +441,134 @@ impl Eq for Capabilities {}
 
 impl PartialEq for Capabilities {
     fn eq(&self, other: &Self) -> bool {
-        self.my_values == other.my_values
+        if Arc::ptr_eq(&self.my_schema, &other.my_schema) {
+            return self.my_row == other.my_row;
+        }
+        self.raw_values().eq(other.raw_values())
     }
 }
 
-pub fn init_default_capa<'a>(fields: &Vec<&'static BrowsCapField>) -> &'a Capabilities {
-    DEFAULT_CAPABILITIES.get_or_init(|| {
-        let mut result: Vec<Ustr> = Vec::new();
-        for _i in 0..fields.len() {
-            result.push(Ustr::from(capabilities::UNKNOWN_BROWSCAP_VALUE));
-        }
-        Capabilities::new(result)
-    })
-}
+#[cfg(test)]
+mod test_capabilities {
+    use super::*;
+    use crate::{DEVICE_NAME, IS_CRAWLER};
 
-/**
- * 缓存capabilities
- */
-pub fn get_capabilities(values: Vec<Ustr>, capa_cache: &CapaCache) -> Arc<Capabilities> {
-    let capabilities: Capabilities = Capabilities::new(values);
-    capa_cache.get_or_insert(capabilities)
-}
+    #[test]
+    fn test_bits() {
+        assert_eq!(bits_needed(1), 0);
+        assert_eq!(bits_needed(2), 1);
+        assert_eq!(bits_needed(256), 8);
+        assert_eq!(bits_needed(257), 9);
 
-pub fn init_wild_card_capa<'a>(fields: &'a Vec<&'static BrowsCapField>) -> Arc<Capabilities> {
-    let mut values = init_default_capa(fields).my_values.clone();
-    let mapper = MAPPER.get().unwrap().read().unwrap();
-    for (index, item) in values.iter_mut().enumerate() {
-        let position_field = mapper.position_field(index);
-        if let Some(field) = position_field {
-            if *field == BROWSER || *field == BROWSER_TYPE {
-                *item = Ustr::from("Default Browser");
-                continue;
-            }
-            if field.is_default {
-                *item = Ustr::from(capabilities::UNKNOWN_BROWSCAP_VALUE);
-            }
-        }
+        let mut row = [0u64; 2];
+        write_bits(&mut row, 3, 5);
+        write_bits(&mut row, 60, 0x3ff);
+        assert_eq!(read_bits(&row, 3, 3), 5);
+        assert_eq!(read_bits(&row, 60, 10), 0x3ff);
+        assert_eq!(read_bits(&row, 70, 0), 0);
+    }
+
+    #[test]
+    fn test_capa_cache() {
+        let mut cache = CapaCache::new(vec![&BROWSER, &IS_CRAWLER]);
+        let chrome = cache.get_or_insert(&["Chrome", "false"]);
+        let bot = cache.get_or_insert(&["Googlebot", "true"]);
+        assert_eq!(chrome, cache.get_or_insert(&["Chrome", "false"]));
+        let table = cache.finish(&ParserOptions::default());
+
+        assert_eq!(table.get(chrome).get_browser(), Some("Chrome"));
+        assert_eq!(table.get(bot).get_value(&IS_CRAWLER), Some("true"));
+        assert_eq!(table.get(bot).get_value(&DEVICE_NAME), None);
+        let unmatched = table.get_fallback(Fallback::Unmatched);
+        assert_eq!(unmatched.get_browser(), Some(DEFAULT_BROWSER_VALUE));
+        let empty = table.get_fallback(Fallback::Empty);
+        assert_eq!(empty.get_value(&IS_CRAWLER), Some(UNKNOWN_BROWSCAP_VALUE));
+
+        // 不同的字典，相同的值
+        let mut other = CapaCache::new(vec![&BROWSER, &IS_CRAWLER]);
+        other.get_or_insert(&["Googlebot", "true"]);
+        let other_chrome = other.get_or_insert(&["Chrome", "false"]);
+        let other_table = other.finish(&ParserOptions {
+            unknown_value: "?".into(),
+            ..ParserOptions::default()
+        });
+        assert_eq!(table.get(chrome), other_table.get(other_chrome));
+        assert_ne!(table.get(bot), other_table.get(other_chrome));
+        let other_empty = other_table.get_fallback(Fallback::Empty);
+        assert_eq!(empty, other_empty);
+        assert_eq!(other_empty.get_browser(), Some("?"));
+    }
+
+    #[test]
+    fn test_field_value() {
+        let mut cache = CapaCache::new(vec![&BROWSER, &IS_CRAWLER]);
+        let unknown = cache.get_or_insert(&[UNKNOWN_BROWSCAP_VALUE, MISSING_VALUE]);
+        let table = cache.finish(&ParserOptions {
+            unknown_value: "n/a".into(),
+            ..ParserOptions::default()
+        });
+
+        let capabilities = table.get(unknown);
+        assert_eq!(capabilities.get_field(&BROWSER), FieldValue::Value("Unknown"));
+        assert_eq!(capabilities.get_field(&IS_CRAWLER), FieldValue::Unknown);
+        assert_eq!(capabilities.get_field(&DEVICE_NAME), FieldValue::NotLoaded);
+        assert_eq!(capabilities.get_value(&BROWSER), Some("Unknown"));
+        assert_eq!(capabilities.get_value(&IS_CRAWLER), Some("n/a"));
+        let unmatched = table.get_fallback(Fallback::Unmatched);
+        assert_eq!(unmatched.get_field(&IS_CRAWLER), FieldValue::Unknown);
+        let empty = table.get_fallback(Fallback::Empty);
+        assert_eq!(empty.get_field(&BROWSER), FieldValue::Unknown);
+        assert_eq!(FieldValue::Value("true").value(), Some("true"));
+        assert_eq!(FieldValue::Unknown.value(), None);
+    }
+
+    #[test]
+    fn test_custom_fallbacks() {
+        let mut options = ParserOptions::default();
+        options.fallbacks[Fallback::Empty as usize] =
+            Some(vec![(&BROWSER, "No UA".into()), (&DEVICE_NAME, "ignored".into())]);
+        options.fallbacks[Fallback::Unmatched as usize] =
+            Some(vec![(&IS_CRAWLER, "x".into()), (&IS_CRAWLER, " true ".into())]);
+        let mut cache = CapaCache::new(vec![&BROWSER, &IS_CRAWLER]);
+        cache.get_or_insert(&["Chrome", "false"]);
+        let table = cache.finish(&options);
+
+        let empty = table.get_fallback(Fallback::Empty);
+        assert_eq!(empty.get_field(&BROWSER), FieldValue::Value("No UA"));
+        assert_eq!(empty.get_field(&IS_CRAWLER), FieldValue::Unknown);
+        assert_eq!(empty.get_field(&DEVICE_NAME), FieldValue::NotLoaded);
+        let unmatched = table.get_fallback(Fallback::Unmatched);
+        assert_eq!(unmatched.get_field(&BROWSER), FieldValue::Unknown);
+        assert_eq!(unmatched.get_field(&IS_CRAWLER), FieldValue::Value("true"));
+        let too_long = table.get_fallback(Fallback::TooLong);
+        assert_eq!(too_long.get_field(&BROWSER), FieldValue::Unknown);
+    }
+
+    #[test]
+    fn test_push_custom() {
+        let mut cache = CapaCache::new(vec![&BROWSER, &IS_CRAWLER]);
+        let chrome = cache.get_or_insert(&["Chrome", "false"]);
+        let mut table = cache.finish(&ParserOptions {
+            unknown_value: "n/a".into(),
+            ..ParserOptions::default()
+        });
+        let rows = table.len();
+        let loaded = table.clone();
+
+        let bot = table.push_custom(&[(&BROWSER, "PartnerBot".into()), (&DEVICE_NAME, "x".into())]);
+        assert_eq!(bot as usize, rows);
+        let capabilities = table.get(bot);
+        assert_eq!(capabilities.get_field(&BROWSER), FieldValue::Value("PartnerBot"));
+        assert_eq!(capabilities.get_value(&IS_CRAWLER), Some("n/a"));
+        assert_eq!(capabilities.get_field(&DEVICE_NAME), FieldValue::NotLoaded);
+
+        let same = table.push_custom(&[(&BROWSER, "Chrome".into()), (&IS_CRAWLER, "false".into())]);
+        assert_eq!(table.get(same), table.get(chrome));
+
+        // 加载时的行仍然共享，追加的行共用一个字段布局
+        assert!(Arc::ptr_eq(&table.my_rows, &loaded.my_rows));
+        assert_eq!(loaded.len(), rows);
+        assert_eq!(table.get(bot).get_browser(), Some("PartnerBot"));
+        assert!(Arc::ptr_eq(&table.get(bot).my_schema, &table.get(same).my_schema));
     }
-    Arc::new(Capabilities::new(values))
 }
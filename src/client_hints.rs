@@ -0,0 +1,587 @@
+use crate::{
+    BROWSER, BROWSER_MAJOR_VERSION, BROWSER_MINOR_VERSION, BROWSER_VERSION, BrowsCapField,
+    Capabilities, ClientHints, DEVICE_CODE_NAME, DEVICE_NAME, Fallback, FieldValue,
+    HintedCapabilities, IS_MOBILE_DEVICE, PLATFORM, PLATFORM_VERSION, ParseOutcome, Provenance,
+    UserAgentParser,
+};
+
+//Client Hints中的品牌名与browscap中浏览器名不同的部分
+const BRAND_NAMES: [(&str, &str); 4] = [
+    ("Google Chrome", "Chrome"),
+    ("Microsoft Edge", "Edge"),
+    ("Samsung Internet", "Samsung Browser"),
+    ("Yandex", "Yandex Browser"),
+];
+
+impl UserAgentParser {
+    /// Parses `user_agent` and overlays the values from `hints`, see [`ClientHints::apply`].
+    pub fn parse_with_hints(
+        &self,
+        user_agent: &str,
+        hints: &ClientHints,
+    ) -> HintedCapabilities<'_> {
+        hints.apply(self.parse(user_agent))
+    }
+}
+
+impl<'h> ClientHints<'h> {
+    /// Takes the `Sec-CH-UA*` headers from `headers`, matching names case-insensitively.
+    pub fn from_headers(headers: impl IntoIterator<Item = (&'h str, &'h str)>) -> ClientHints<'h> {
+        let mut hints = ClientHints::default();
+        for (name, value) in headers {
+            let slot = match name.to_ascii_lowercase().as_str() {
+                "sec-ch-ua" => &mut hints.ua,
+                "sec-ch-ua-full-version-list" => &mut hints.full_version_list,
+                "sec-ch-ua-platform" => &mut hints.platform,
+                "sec-ch-ua-platform-version" => &mut hints.platform_version,
+                "sec-ch-ua-mobile" => &mut hints.mobile,
+                "sec-ch-ua-model" => &mut hints.model,
+                _ => continue,
+            };
+            *slot = Some(value);
+        }
+        hints
+    }
+
+    /// Overlays the values of these hints onto `capabilities`. Only loaded fields are
+    /// overlaid, and malformed or empty headers are ignored:
+    ///
+    /// * the main brand of `Sec-CH-UA-Full-Version-List`, or of `Sec-CH-UA` with only the
+    ///   major version, sets the browser, its version and its major and minor version. From
+    ///   `Sec-CH-UA` the minor version is 0, so the version is `"{major}.0"`. GREASE brands
+    ///   such as `"Not/A)Brand"` are skipped
+    /// * `Sec-CH-UA-Platform` and `Sec-CH-UA-Platform-Version` set the platform and its
+    ///   version, telling Windows 11 from Windows 10
+    /// * `Sec-CH-UA-Mobile` sets whether it is a mobile device
+    /// * `Sec-CH-UA-Model` sets the device name and code name
+    pub fn apply<'a>(&self, capabilities: &'a Capabilities) -> HintedCapabilities<'a> {
+        let outcome = ParseOutcome {
+            capabilities,
+            fallback: None,
+        };
+        let mut hinted = HintedCapabilities::new(outcome, Provenance::UserAgent);
+        self.overlay(&mut hinted);
+        hinted
+    }
+
+    pub(crate) fn overlay(&self, hinted: &mut HintedCapabilities) {
+        let mut overrides = Vec::new();
+        self.add_browser(&mut overrides);
+        self.add_platform(&mut overrides);
+        if let Some(BareItem::Boolean(mobile)) = self.mobile.and_then(parse_item) {
+            overrides.push((&IS_MOBILE_DEVICE, mobile.to_string()));
+        }
+        if let Some(BareItem::String(model)) = self.model.and_then(parse_item)
+            && !model.is_empty()
+        {
+            overrides.push((&DEVICE_NAME, model.clone()));
+            overrides.push((&DEVICE_CODE_NAME, model));
+        }
+        for (field, value) in overrides {
+            hinted.set(field, value, Provenance::ClientHints);
+        }
+    }
+
+    fn add_browser(&self, overrides: &mut Vec<(&'static BrowsCapField, String)>) {
+        let full_versions = self.full_version_list.and_then(parse_brands);
+        let major_versions = self.ua.and_then(parse_brands);
+        let (brand, version, full) = match (
+            full_versions.as_deref().and_then(main_brand),
+            major_versions.as_deref().and_then(main_brand),
+        ) {
+            (Some((brand, version)), _) => (brand, version, true),
+            (None, Some((brand, version))) => (brand, version, false),
+            (None, None) => return,
+        };
+        let browser = BRAND_NAMES
+            .iter()
+            .find(|(name, _)| *name == brand)
+            .map_or(brand, |(_, browser)| browser);
+        overrides.push((&BROWSER, browser.to_string()));
+        let mut parts = version.split('.');
+        let Some(major) = parts.next().filter(|major| !major.is_empty()) else {
+            return;
+        };
+        // Sec-CH-UA只有主版本，次版本按0算，不能留着UA中另一个主版本的值
+        let minor = parts.next().filter(|_| full).unwrap_or("0");
+        overrides.push((&BROWSER_MAJOR_VERSION, major.to_string()));
+        overrides.push((&BROWSER_MINOR_VERSION, minor.to_string()));
+        overrides.push((&BROWSER_VERSION, format!("{major}.{minor}")));
+    }
+
+    fn add_platform(&self, overrides: &mut Vec<(&'static BrowsCapField, String)>) {
+        let Some(BareItem::String(platform)) = self.platform.and_then(parse_item) else {
+            return;
+        };
+        let version = match self.platform_version.and_then(parse_item) {
+            Some(BareItem::String(version)) => version,
+            _ => String::new(),
+        };
+        let mut parts = version.split('.');
+        let major: Option<u32> = parts.next().and_then(|major| major.parse().ok());
+        let minor = parts.next().unwrap_or("0");
+        //Windows的platform version是Windows API合约版本，13以上是Windows 11
+        let (platform, version) = match (platform.as_str(), major) {
+            ("Windows", Some(13..)) => ("Win11", Some("11.0".to_string())),
+            ("Windows", Some(1..=12)) => ("Win10", Some("10.0".to_string())),
+            ("Windows", _) => return,
+            ("Chrome OS", major) => ("ChromeOS", major.map(|major| format!("{major}.{minor}"))),
+            (platform, major) => (platform, major.map(|major| format!("{major}.{minor}"))),
+        };
+        if platform.is_empty() || platform == "Unknown" {
+            return;
+        }
+        overrides.push((&PLATFORM, platform.to_string()));
+        if let Some(version) = version {
+            overrides.push((&PLATFORM_VERSION, version));
+        }
+    }
+}
+
+//第一个既不是GREASE也不是Chromium的品牌，没有时取Chromium
+fn main_brand(brands: &[(String, String)]) -> Option<(&str, &str)> {
+    let brands = brands.iter().filter(|(brand, _)| !is_grease(brand));
+    let mut chromium = None;
+    for (brand, version) in brands {
+        if brand == "Chromium" {
+            chromium = Some((brand.as_str(), version.as_str()));
+        } else if !brand.is_empty() {
+            return Some((brand, version));
+        }
+    }
+    chromium
+}
+
+/*
+    UA-CH规范中的GREASE品牌：Not?A?Brand一族（如"Not/A)Brand"、" Not A;Brand"），
+    或者含有正常品牌名中不会出现的字符，正常品牌名只由ASCII字母、数字和空格组成
+*/
+fn is_grease(brand: &str) -> bool {
+    let name = brand.trim().as_bytes();
+    let family = name.len() == 11
+        && name.starts_with(b"Not")
+        && name[4] == b'A'
+        && name.ends_with(b"Brand");
+    family || !brand.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ')
+}
+
+//"Brand";v="version"组成的列表
+fn parse_brands(value: &str) -> Option<Vec<(String, String)>> {
+    let brands = parse_list(value)?
+        .into_iter()
+        .filter_map(|(item, params)| {
+            let BareItem::String(brand) = item else {
+                return None;
+            };
+            let version = params.into_iter().find_map(|(key, value)| match value {
+                BareItem::String(version) if key == "v" => Some(version),
+                _ => None,
+            });
+            Some((brand, version.unwrap_or_default()))
+        })
+        .collect();
+    Some(brands)
+}
+
+impl<'a> HintedCapabilities<'a> {
+    //source是outcome匹配自的header，UserAgent或DeviceUserAgent
+    pub(crate) fn new(outcome: ParseOutcome<'a>, source: Provenance) -> HintedCapabilities<'a> {
+        HintedCapabilities {
+            my_capabilities: outcome.capabilities,
+            my_fallback: outcome.fallback,
+            my_source: source,
+            my_overrides: Vec::new(),
+        }
+    }
+
+    //未加载的字段被忽略，同一字段设置多次时后设置的优先
+    pub(crate) fn set(&mut self, field: &'static BrowsCapField, value: String, source: Provenance) {
+        if self.my_capabilities.get_field(field) != FieldValue::NotLoaded {
+            self.my_overrides.push((field, value, source));
+        }
+    }
+
+    /// The capabilities matched from the user agent, without the hints.
+    pub fn capabilities(&self) -> &'a Capabilities {
+        self.my_capabilities
+    }
+
+    /// Set when [`HintedCapabilities::capabilities`] are fallback capabilities, see
+    /// [`ParseOutcome`].
+    pub fn fallback(&self) -> Option<Fallback> {
+        self.my_fallback
+    }
+
+    /// Like [`Capabilities::get_value`], preferring the value from the hints.
+    pub fn get_value(&self, field: &BrowsCapField) -> Option<&str> {
+        match self.get_override(field) {
+            Some((value, _)) => Some(value),
+            None => self.my_capabilities.get_value(field),
+        }
+    }
+
+    /// Like [`Capabilities::get_field`], preferring the value from the hints.
+    pub fn get_field(&self, field: &BrowsCapField) -> FieldValue<'_> {
+        match self.get_override(field) {
+            Some((value, _)) => FieldValue::Value(value),
+            None => self.my_capabilities.get_field(field),
+        }
+    }
+
+    /// Where the value of `field` came from, `None` when it was not loaded.
+    pub fn provenance(&self, field: &BrowsCapField) -> Option<Provenance> {
+        match self.get_override(field) {
+            Some((_, source)) => Some(source),
+            None => self
+                .my_capabilities
+                .get_value(field)
+                .map(|_| self.my_source),
+        }
+    }
+
+    /// Every loaded field with its value and where it came from, in ordinal order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static BrowsCapField, &str, Provenance)> {
+        self.my_capabilities
+            .iter()
+            .map(|(field, value)| match self.get_override(field) {
+                Some((value, source)) => (field, value, source),
+                None => (field, value, self.my_source),
+            })
+    }
+
+    fn get_override(&self, field: &BrowsCapField) -> Option<(&str, Provenance)> {
+        self.my_overrides
+            .iter()
+            .rev()
+            .find(|(overridden, _, _)| *overridden == field)
+            .map(|(_, value, source)| (value.as_str(), *source))
+    }
+}
+
+/*
+    RFC 8941 Structured Field Values中Client Hints用到的部分：
+    由bare item和参数组成的item与list。inner list和dictionary不支持
+*/
+#[derive(Debug, Clone, PartialEq)]
+enum BareItem {
+    String(String),
+    Token(String),
+    Boolean(bool),
+    Number(String),
+    Bytes(String),
+}
+
+type Parameters = Vec<(String, BareItem)>;
+
+fn parse_item(value: &str) -> Option<BareItem> {
+    let mut parser = Parser::new(value);
+    let item = parser.parse_bare_item()?;
+    parser.parse_parameters()?;
+    parser.skip_spaces();
+    parser.at_end().then_some(item)
+}
+
+fn parse_list(value: &str) -> Option<Vec<(BareItem, Parameters)>> {
+    let mut parser = Parser::new(value);
+    let mut result = Vec::new();
+    parser.skip_spaces();
+    while !parser.at_end() {
+        let item = parser.parse_bare_item()?;
+        result.push((item, parser.parse_parameters()?));
+        parser.skip_whitespace();
+        if parser.at_end() {
+            break;
+        }
+        parser.expect(b',')?;
+        parser.skip_whitespace();
+        if parser.at_end() {
+            return None;
+        }
+    }
+    Some(result)
+}
+
+struct Parser<'v> {
+    my_input: &'v [u8],
+    my_pos: usize,
+}
+
+impl<'v> Parser<'v> {
+    fn new(value: &'v str) -> Parser<'v> {
+        let value = value.trim_matches(|c| c == ' ' || c == '\t');
+        Parser {
+            my_input: value.as_bytes(),
+            my_pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.my_input.get(self.my_pos).copied()
+    }
+
+    fn at_end(&self) -> bool {
+        self.my_pos >= self.my_input.len()
+    }
+
+    fn expect(&mut self, expected: u8) -> Option<()> {
+        (self.peek()? == expected).then(|| self.my_pos += 1)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.my_pos += 1;
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.my_pos += 1;
+        }
+    }
+
+    //连续满足accept的字节
+    fn take_while(&mut self, accept: impl Fn(u8) -> bool) -> &'v str {
+        let start = self.my_pos;
+        while self.peek().is_some_and(&accept) {
+            self.my_pos += 1;
+        }
+        // 只接受ASCII字节，切片一定是合法的UTF-8
+        std::str::from_utf8(&self.my_input[start..self.my_pos]).unwrap()
+    }
+
+    fn parse_bare_item(&mut self) -> Option<BareItem> {
+        match self.peek()? {
+            b'"' => self.parse_string(),
+            b'?' => {
+                self.my_pos += 1;
+                let value = match self.peek()? {
+                    b'0' => false,
+                    b'1' => true,
+                    _ => return None,
+                };
+                self.my_pos += 1;
+                Some(BareItem::Boolean(value))
+            }
+            b':' => {
+                self.my_pos += 1;
+                let bytes = self.take_while(|c| c.is_ascii_alphanumeric() || b"+/=".contains(&c));
+                let bytes = bytes.to_string();
+                self.expect(b':')?;
+                Some(BareItem::Bytes(bytes))
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = self.my_pos;
+                self.my_pos += 1;
+                self.take_while(|c| c.is_ascii_digit() || c == b'.');
+                let number = std::str::from_utf8(&self.my_input[start..self.my_pos]).unwrap();
+                Some(BareItem::Number(number.to_string()))
+            }
+            c if c.is_ascii_alphabetic() || c == b'*' => {
+                let token = self.take_while(|c| is_tchar(c) || c == b':' || c == b'/');
+                Some(BareItem::Token(token.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_string(&mut self) -> Option<BareItem> {
+        self.expect(b'"')?;
+        let mut result = String::new();
+        loop {
+            match self.peek()? {
+                b'"' => {
+                    self.my_pos += 1;
+                    return Some(BareItem::String(result));
+                }
+                b'\\' => {
+                    self.my_pos += 1;
+                    let escaped = self.peek().filter(|c| *c == b'"' || *c == b'\\')?;
+                    result.push(escaped as char);
+                }
+                c @ 0x20..=0x7e => result.push(c as char),
+                _ => return None,
+            }
+            self.my_pos += 1;
+        }
+    }
+
+    fn parse_parameters(&mut self) -> Option<Parameters> {
+        let mut result = Parameters::new();
+        while self.peek() == Some(b';') {
+            self.my_pos += 1;
+            self.skip_spaces();
+            if !self
+                .peek()
+                .is_some_and(|c| c.is_ascii_lowercase() || c == b'*')
+            {
+                return None;
+            }
+            let key = self.take_while(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || b"_-.*".contains(&c)
+            });
+            let value = if self.peek() == Some(b'=') {
+                self.my_pos += 1;
+                self.parse_bare_item()?
+            } else {
+                BareItem::Boolean(true)
+            };
+            result.push((key.to_string(), value));
+        }
+        Some(result)
+    }
+}
+
+fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+#[cfg(test)]
+mod test_client_hints {
+    use super::*;
+    use crate::capabilities::CapaCache;
+    use crate::{PLATFORM_MAKER, ParserOptions};
+
+    #[test]
+    fn test_parse_structured_fields() {
+        assert_eq!(
+            parse_item("\"Windows\""),
+            Some(BareItem::String("Windows".into()))
+        );
+        assert_eq!(parse_item(" ?1 "), Some(BareItem::Boolean(true)));
+        assert_eq!(
+            parse_item("\"a \\\"b\\\" \\\\\""),
+            Some(BareItem::String("a \"b\" \\".into()))
+        );
+        assert_eq!(parse_item("?2"), None);
+        assert_eq!(parse_item("\"open"), None);
+        assert_eq!(parse_item("\"a\" x"), None);
+
+        let list = parse_list(
+            "\"Chromium\";v=\"124\", \"Google Chrome\";v=\"124\",\t\"Not-A.Brand\";v=\"99\"",
+        )
+        .unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[1].0, BareItem::String("Google Chrome".into()));
+        assert_eq!(
+            list[1].1,
+            vec![("v".to_string(), BareItem::String("124".into()))]
+        );
+        assert_eq!(parse_list("a;q=0.5;x, :aGk=:").unwrap().len(), 2);
+        assert_eq!(parse_list("").unwrap().len(), 0);
+        assert!(parse_list("\"a\",").is_none());
+        assert!(parse_list("\"a\";V=1").is_none());
+    }
+
+    #[test]
+    fn test_main_brand() {
+        let brands = parse_brands(
+            "\" Not A;Brand\";v=\"99\", \"Chromium\";v=\"124\", \"Microsoft Edge\";v=\"124\"",
+        )
+        .unwrap();
+        assert_eq!(main_brand(&brands), Some(("Microsoft Edge", "124")));
+        let brands = parse_brands("\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"120\"").unwrap();
+        assert_eq!(main_brand(&brands), Some(("Chromium", "120")));
+        let brands = parse_brands("\"Not_A Brand\";v=\"8\"").unwrap();
+        assert_eq!(main_brand(&brands), None);
+
+        for grease in ["Not/A)Brand", "Not?A_Brand", "Not A(Brand", " Not A;Brand", "(Brand:"] {
+            assert!(is_grease(grease), "{grease}");
+        }
+        for brand in ["Google Chrome", "Opera GX", "Not A Brand Browser", "Brand New"] {
+            assert!(!is_grease(brand), "{brand}");
+        }
+        let brands = parse_brands("\"Not A Brand Browser\";v=\"3\", \"Chromium\";v=\"120\"");
+        assert_eq!(main_brand(&brands.unwrap()), Some(("Not A Brand Browser", "3")));
+    }
+
+    #[test]
+    fn test_apply() {
+        let fields = vec![
+            &BROWSER,
+            &BROWSER_VERSION,
+            &BROWSER_MAJOR_VERSION,
+            &PLATFORM,
+            &PLATFORM_VERSION,
+            &PLATFORM_MAKER,
+            &IS_MOBILE_DEVICE,
+        ];
+        let mut cache = CapaCache::new(fields);
+        let row = cache.get_or_insert(&[
+            "Chrome",
+            "124.0",
+            "124",
+            "Win10",
+            "10.0",
+            "Microsoft",
+            "false",
+        ]);
+        let table = cache.finish(&ParserOptions::default());
+        let capabilities = table.get(row);
+
+        let hints = ClientHints::from_headers([
+            (
+                "Sec-CH-UA",
+                "\"Chromium\";v=\"125\", \"Google Chrome\";v=\"125\"",
+            ),
+            (
+                "sec-ch-ua-full-version-list",
+                "\"Chromium\";v=\"125.0.6422.60\", \"Google Chrome\";v=\"125.1.6422.60\"",
+            ),
+            ("Sec-CH-UA-Platform", "\"Windows\""),
+            ("Sec-CH-UA-Platform-Version", "\"15.0.0\""),
+            ("Sec-CH-UA-Mobile", "?0"),
+            ("Sec-CH-UA-Model", "\"\""),
+            ("Accept", "*/*"),
+        ]);
+        let hinted = hints.apply(capabilities);
+        assert_eq!(hinted.get_value(&BROWSER), Some("Chrome"));
+        assert_eq!(hinted.get_value(&BROWSER_VERSION), Some("125.1"));
+        assert_eq!(hinted.get_value(&BROWSER_MAJOR_VERSION), Some("125"));
+        assert_eq!(hinted.get_value(&PLATFORM), Some("Win11"));
+        assert_eq!(hinted.get_value(&PLATFORM_VERSION), Some("11.0"));
+        assert_eq!(hinted.get_value(&IS_MOBILE_DEVICE), Some("false"));
+        assert_eq!(
+            hinted.get_field(&BROWSER_MINOR_VERSION),
+            FieldValue::NotLoaded
+        );
+        assert_eq!(hinted.get_field(&DEVICE_NAME), FieldValue::NotLoaded);
+        assert_eq!(
+            hinted.provenance(&PLATFORM_VERSION),
+            Some(Provenance::ClientHints)
+        );
+        assert_eq!(
+            hinted.provenance(&PLATFORM_MAKER),
+            Some(Provenance::UserAgent)
+        );
+        assert_eq!(hinted.provenance(&DEVICE_NAME), None);
+        assert_eq!(hinted.capabilities(), capabilities);
+        let sources: Vec<Provenance> = hinted.iter().map(|(_, _, source)| source).collect();
+        assert_eq!(
+            sources
+                .iter()
+                .filter(|s| **s == Provenance::UserAgent)
+                .count(),
+            1
+        );
+
+        let hints = ClientHints {
+            ua: Some("\"Google Chrome\";v=\"126\""),
+            platform: Some("\"Android\""),
+            platform_version: Some("\"14.0.0\""),
+            full_version_list: Some("garbage;"),
+            ..ClientHints::default()
+        };
+        let hinted = hints.apply(capabilities);
+        assert_eq!(hinted.get_value(&BROWSER_MAJOR_VERSION), Some("126"));
+        assert_eq!(hinted.get_value(&BROWSER_VERSION), Some("126.0"));
+        assert_eq!(hinted.get_value(&PLATFORM), Some("Android"));
+        assert_eq!(hinted.get_value(&PLATFORM_VERSION), Some("14.0"));
+
+        let hinted = ClientHints::default().apply(capabilities);
+        assert!(
+            hinted
+                .iter()
+                .all(|(_, _, source)| source == Provenance::UserAgent)
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use crate::DEFAULT_FILE_NAME;
+use crate::error::ParseError;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+//指定数据文件路径的环境变量
+pub(crate) const FILE_ENV: &str = "BROWSCAP_FILE";
+//XDG数据目录下存放数据文件的子目录
+const DATA_DIR: &str = "browscap";
+
+/*
+    按顺序列出可能的数据文件位置：指定了路径时只用这个路径；否则依次是BROWSCAP_FILE、
+    $XDG_DATA_HOME和$XDG_DATA_DIRS下的browscap目录、调用方给的后备路径，最后是当前目录
+*/
+pub(crate) fn candidates(
+    explicit: Option<&Path>,
+    fallbacks: &[PathBuf],
+    var: impl Fn(&str) -> Option<OsString>,
+) -> Vec<PathBuf> {
+    if let Some(explicit) = explicit {
+        return vec![explicit.to_path_buf()];
+    }
+    // 空的环境变量视为未设置
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    let mut result = Vec::new();
+    if let Some(file) = var(FILE_ENV) {
+        result.push(PathBuf::from(file));
+    }
+    let data_home = match var("XDG_DATA_HOME") {
+        Some(data_home) => Some(PathBuf::from(data_home)),
+        None => var("HOME").map(|home| Path::new(&home).join(".local/share")),
+    };
+    let data_dirs = var("XDG_DATA_DIRS").unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+    // XDG规范要求忽略相对路径
+    for dir in data_home.into_iter().chain(env::split_paths(&data_dirs)) {
+        if dir.is_absolute() {
+            result.push(dir.join(DATA_DIR).join(DEFAULT_FILE_NAME));
+        }
+    }
+    result.extend(fallbacks.iter().cloned());
+    result.push(PathBuf::from(DEFAULT_FILE_NAME));
+    result
+}
+
+//返回第一个存在的文件，都不存在时错误中列出所有尝试过的路径
+pub(crate) fn resolve(explicit: Option<&Path>, fallbacks: &[PathBuf]) -> Result<PathBuf, ParseError> {
+    let candidates = candidates(explicit, fallbacks, |name| env::var_os(name));
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(ParseError::FileNotFound(candidates)),
+    }
+}
+
+//扩展名为.ini的文件按browscap的INI格式读取，其他的都按CSV读取
+pub(crate) fn is_ini(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ini"))
+}
+
+#[cfg(test)]
+mod test_data_path {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn candidates_with(vars: &[(&str, &str)], fallbacks: &[PathBuf]) -> Vec<PathBuf> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        candidates(None, fallbacks, |name| vars.get(name).map(OsString::from))
+    }
+
+    #[test]
+    fn test_candidates() {
+        let fallbacks = vec![PathBuf::from("/opt/app/browscap.csv")];
+        let paths = candidates_with(
+            &[
+                (FILE_ENV, "/etc/browscap.csv"),
+                ("XDG_DATA_HOME", "/home/app/data"),
+                ("XDG_DATA_DIRS", "/usr/share:relative/share:"),
+            ],
+            &fallbacks,
+        );
+        let expected: Vec<PathBuf> = [
+            "/etc/browscap.csv",
+            "/home/app/data/browscap/browscap_sorted.csv",
+            "/usr/share/browscap/browscap_sorted.csv",
+            "/opt/app/browscap.csv",
+            "browscap_sorted.csv",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(paths, expected);
+
+        let paths = candidates_with(&[(FILE_ENV, ""), ("HOME", "/home/app")], &[]);
+        let expected: Vec<PathBuf> = [
+            "/home/app/.local/share/browscap/browscap_sorted.csv",
+            "/usr/local/share/browscap/browscap_sorted.csv",
+            "/usr/share/browscap/browscap_sorted.csv",
+            "browscap_sorted.csv",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(paths, expected);
+
+        let explicit = PathBuf::from("data/browscap.csv");
+        let paths = candidates(Some(&explicit), &fallbacks, |_| Some("/x".into()));
+        assert_eq!(paths, vec![explicit]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let missing = PathBuf::from("does/not/exist.csv");
+        let error = resolve(Some(&missing), &[]).unwrap_err();
+        assert!(matches!(&error, ParseError::FileNotFound(tried) if tried == &vec![missing]));
+        assert_eq!(
+            error.to_string(),
+            "cannot find the browscap file, tried: does/not/exist.csv"
+        );
+        let found = resolve(Some(Path::new(DEFAULT_FILE_NAME)), &[]).unwrap();
+        assert_eq!(found, PathBuf::from(DEFAULT_FILE_NAME));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{BrowsCapField, DatasetDiff, FieldChange, UserAgentParser};
+
+//每种变化默认保留的示例数
+const DEFAULT_MAX_EXAMPLES: usize = 3;
+
+impl DatasetDiff {
+    /// Compares `fields`, which both parsers have to load. Values are those of
+    /// [`Capabilities::get_value`](crate::Capabilities::get_value).
+    pub fn new(fields: &[&'static BrowsCapField]) -> DatasetDiff {
+        DatasetDiff {
+            my_fields: fields.to_vec(),
+            my_max_examples: DEFAULT_MAX_EXAMPLES,
+            my_total: 0,
+            my_changed: 0,
+            my_changes: Default::default(),
+        }
+    }
+
+    /// How many user agents to keep as examples of each change, 3 by default.
+    pub fn max_examples(mut self, max_examples: usize) -> DatasetDiff {
+        self.my_max_examples = max_examples;
+        self
+    }
+
+    /// Parses `user_agent` with both parsers and records the fields whose values differ.
+    pub fn add(&mut self, old: &UserAgentParser, new: &UserAgentParser, user_agent: &str) {
+        let old_capabilities = old.parse(user_agent);
+        let new_capabilities = new.parse(user_agent);
+        self.my_total += 1;
+        let mut changed = false;
+        for (i, field) in self.my_fields.iter().enumerate() {
+            let old_value = old_capabilities.get_value(field).unwrap_or_default();
+            let new_value = new_capabilities.get_value(field).unwrap_or_default();
+            if old_value == new_value {
+                continue;
+            }
+            changed = true;
+            let (count, examples) = self
+                .my_changes
+                .entry((i, old_value.to_string(), new_value.to_string()))
+                .or_default();
+            *count += 1;
+            if examples.len() < self.my_max_examples {
+                examples.push(user_agent.to_string());
+            }
+        }
+        if changed {
+            self.my_changed += 1;
+        }
+    }
+
+    /// User agents compared.
+    pub fn total(&self) -> u64 {
+        self.my_total
+    }
+
+    /// User agents for which at least one field changed.
+    pub fn changed(&self) -> u64 {
+        self.my_changed
+    }
+
+    /// User agents for which `field` changed.
+    pub fn changed_for(&self, field: &BrowsCapField) -> u64 {
+        self.my_changes
+            .iter()
+            .filter(|((i, _, _), _)| self.my_fields[*i] == field)
+            .map(|(_, (count, _))| count)
+            .sum()
+    }
+
+    /// Every change, grouped by field in the order the fields were given, the most frequent
+    /// first.
+    pub fn changes(&self) -> Vec<FieldChange<'_>> {
+        let mut changes: Vec<(usize, FieldChange)> = self
+            .my_changes
+            .iter()
+            .map(|((i, old, new), (count, examples))| {
+                let change = FieldChange {
+                    field: self.my_fields[*i],
+                    old,
+                    new,
+                    count: *count,
+                    examples,
+                };
+                (*i, change)
+            })
+            .collect();
+        changes.sort_unstable_by(|(a_field, a), (b_field, b)| {
+            a_field
+                .cmp(b_field)
+                .then(b.count.cmp(&a.count))
+                .then_with(|| (a.old, a.new).cmp(&(b.old, b.new)))
+        });
+        changes.into_iter().map(|(_, change)| change).collect()
+    }
+}
+
+#[cfg(test)]
+mod test_dataset_diff {
+    use super::*;
+    use crate::{BROWSER, DEFAULT_FILE_NAME, FieldSet, PLATFORM};
+    use std::fs;
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+    const IPAD: &str = "Mozilla/5.0 (iPad; CPU OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+    const CHROME: &str = "Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/56.0.2924.87 Safari/537.36";
+
+    #[test]
+    fn test_dataset_diff() {
+        // 把Safari改名为Mobile Safari，模拟新版本的数据
+        let data = fs::read_to_string(DEFAULT_FILE_NAME).unwrap();
+        let path = std::env::temp_dir().join(format!("browscap_diff_{}.csv", std::process::id()));
+        fs::write(&path, data.replace("\"Safari\"", "\"Mobile Safari\"")).unwrap();
+        let old = UserAgentParser::builder().build().unwrap();
+        let new = UserAgentParser::builder().file(&path).build().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut diff = DatasetDiff::new(&FieldSet::DEFAULT.iter().collect::<Vec<_>>()).max_examples(1);
+        for user_agent in [IPHONE, CHROME, IPAD, IPHONE] {
+            diff.add(&old, &new, user_agent);
+        }
+        assert_eq!(diff.total(), 4);
+        assert_eq!(old.parse(IPAD).get_browser(), Some("Safari"));
+        assert_eq!(diff.changed(), 3);
+        assert_eq!(diff.changed_for(&BROWSER), 3);
+        assert_eq!(diff.changed_for(&PLATFORM), 0);
+        assert_eq!(
+            diff.changes(),
+            vec![FieldChange {
+                field: &BROWSER,
+                old: "Safari",
+                new: "Mobile Safari",
+                count: 3,
+                examples: &[IPHONE.to_string()],
+            }]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,5 +9,26 @@ pub enum ParseError{
     FixedPattern,
     #[error("invalid record")]
     InvalidRecord,
-    
+    #[error("cannot compile patterns: {0}")]
+    Regex(#[from] regex::Error),
+    #[error("loading was cancelled")]
+    Cancelled,
+    #[error("cannot find the browscap file, tried: {}", display_paths(.0))]
+    FileNotFound(Vec<PathBuf>),
+    #[error("cannot read {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid log format: {0}")]
+    LogFormat(String),
+    #[error("invalid rule overlay: {0}")]
+    Overlay(String),
+    #[error("cannot resolve parent `{0}`")]
+    Parent(String),
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+    paths.join(", ")
 }
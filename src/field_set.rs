@@ -0,0 +1,247 @@
+use crate::brows_cap_field::VALUES;
+use crate::{
+    BROWSER, BROWSER_MAJOR_VERSION, BROWSER_MAKER, BROWSER_MINOR_VERSION, BROWSER_VERSION,
+    BrowsCapField, COMMENT, DEVICE_POINTING_METHOD, DEVICE_TYPE, FieldSet, IS_CRAWLER,
+    IS_MOBILE_DEVICE, IS_TABLET, PARENT, PLATFORM,
+};
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Sub, SubAssign};
+
+impl FieldSet {
+    /// No fields.
+    pub const EMPTY: FieldSet = FieldSet(0);
+
+    /// The fields loaded by default, those whose [`BrowsCapField::is_default`] is true.
+    pub const DEFAULT: FieldSet = FieldSet::defaults();
+
+    /// The properties of the browscap lite file.
+    pub const LITE: FieldSet = FieldSet::of(&[
+        &PARENT,
+        &COMMENT,
+        &BROWSER,
+        &BROWSER_VERSION,
+        &PLATFORM,
+        &IS_MOBILE_DEVICE,
+        &IS_TABLET,
+        &DEVICE_TYPE,
+    ]);
+
+    /// The properties of the browscap standard file, the lite ones and a few more.
+    pub const STANDARD: FieldSet = FieldSet::LITE.union(FieldSet::of(&[
+        &BROWSER_MAKER,
+        &BROWSER_MAJOR_VERSION,
+        &BROWSER_MINOR_VERSION,
+        &IS_CRAWLER,
+        &DEVICE_POINTING_METHOD,
+    ]));
+
+    /// All fields, as in the browscap full file.
+    pub const FULL: FieldSet = FieldSet(u64::MAX >> (u64::BITS as usize - VALUES.len()));
+
+    /// The set of `fields`, usable in constants.
+    pub const fn of(fields: &[&BrowsCapField]) -> FieldSet {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < fields.len() {
+            bits |= 1 << fields[i].ordinal;
+            i += 1;
+        }
+        FieldSet(bits)
+    }
+
+    const fn defaults() -> FieldSet {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < VALUES.len() {
+            if VALUES[i].is_default {
+                bits |= 1 << VALUES[i].ordinal;
+            }
+            i += 1;
+        }
+        FieldSet(bits)
+    }
+
+    pub const fn union(self, other: FieldSet) -> FieldSet {
+        FieldSet(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: FieldSet) -> FieldSet {
+        FieldSet(self.0 & other.0)
+    }
+
+    /// The fields of `self` that are not in `other`.
+    pub const fn difference(self, other: FieldSet) -> FieldSet {
+        FieldSet(self.0 & !other.0)
+    }
+
+    pub const fn contains(&self, field: &BrowsCapField) -> bool {
+        self.0 & (1 << field.ordinal) != 0
+    }
+
+    pub const fn is_superset(&self, other: FieldSet) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds `field`, returning whether it was not in the set.
+    pub fn insert(&mut self, field: &BrowsCapField) -> bool {
+        let added = !self.contains(field);
+        self.0 |= 1 << field.ordinal;
+        added
+    }
+
+    /// Removes `field`, returning whether it was in the set.
+    pub fn remove(&mut self, field: &BrowsCapField) -> bool {
+        let removed = self.contains(field);
+        self.0 &= !(1 << field.ordinal);
+        removed
+    }
+
+    pub const fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The fields of the set in ordinal order.
+    pub fn iter(&self) -> impl Iterator<Item = &'static BrowsCapField> + use<> {
+        let bits = self.0;
+        VALUES
+            .iter()
+            .filter(move |field| bits & (1 << field.ordinal) != 0)
+    }
+}
+
+impl From<&BrowsCapField> for FieldSet {
+    fn from(field: &BrowsCapField) -> Self {
+        FieldSet::of(&[field])
+    }
+}
+
+impl From<&[&BrowsCapField]> for FieldSet {
+    fn from(fields: &[&BrowsCapField]) -> Self {
+        FieldSet::of(fields)
+    }
+}
+
+impl From<Vec<&'static BrowsCapField>> for FieldSet {
+    fn from(fields: Vec<&'static BrowsCapField>) -> Self {
+        FieldSet::of(&fields)
+    }
+}
+
+impl<'a> FromIterator<&'a BrowsCapField> for FieldSet {
+    fn from_iter<I: IntoIterator<Item = &'a BrowsCapField>>(iter: I) -> Self {
+        let mut set = FieldSet::EMPTY;
+        set.extend(iter);
+        set
+    }
+}
+
+impl<'a> Extend<&'a BrowsCapField> for FieldSet {
+    fn extend<I: IntoIterator<Item = &'a BrowsCapField>>(&mut self, iter: I) {
+        for field in iter {
+            self.insert(field);
+        }
+    }
+}
+
+// 右边可以是FieldSet，也可以是单个field
+impl<T: Into<FieldSet>> BitOr<T> for FieldSet {
+    type Output = FieldSet;
+
+    fn bitor(self, other: T) -> FieldSet {
+        self.union(other.into())
+    }
+}
+
+impl<T: Into<FieldSet>> BitAnd<T> for FieldSet {
+    type Output = FieldSet;
+
+    fn bitand(self, other: T) -> FieldSet {
+        self.intersection(other.into())
+    }
+}
+
+impl<T: Into<FieldSet>> Sub<T> for FieldSet {
+    type Output = FieldSet;
+
+    fn sub(self, other: T) -> FieldSet {
+        self.difference(other.into())
+    }
+}
+
+impl<T: Into<FieldSet>> BitOrAssign<T> for FieldSet {
+    fn bitor_assign(&mut self, other: T) {
+        *self = *self | other;
+    }
+}
+
+impl<T: Into<FieldSet>> BitAndAssign<T> for FieldSet {
+    fn bitand_assign(&mut self, other: T) {
+        *self = *self & other;
+    }
+}
+
+impl<T: Into<FieldSet>> SubAssign<T> for FieldSet {
+    fn sub_assign(&mut self, other: T) {
+        *self = *self - other;
+    }
+}
+
+impl fmt::Debug for FieldSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.iter().map(BrowsCapField::name))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test_field_set {
+    use super::*;
+    use crate::{IS_BETA, PLATFORM_VERSION, RENDERING_ENGINE_MAKER};
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(FieldSet::DEFAULT.len(), 6);
+        assert_eq!(FieldSet::FULL.len(), VALUES.len());
+        assert!(FieldSet::FULL.contains(&RENDERING_ENGINE_MAKER));
+        assert!(FieldSet::STANDARD.is_superset(FieldSet::LITE));
+        assert!(FieldSet::FULL.is_superset(FieldSet::STANDARD | FieldSet::DEFAULT));
+        assert!(!FieldSet::LITE.contains(&IS_CRAWLER));
+        assert_eq!(
+            FieldSet::FULL.iter().collect::<Vec<_>>(),
+            VALUES.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut fields = FieldSet::LITE | &IS_BETA;
+        assert!(fields.contains(&IS_BETA));
+        assert!(!fields.insert(&IS_BETA));
+        assert!(fields.remove(&IS_BETA));
+        assert!(!fields.remove(&IS_BETA));
+        assert_eq!(fields, FieldSet::LITE);
+
+        fields -= FieldSet::of(&[&PARENT, &COMMENT]);
+        assert_eq!(fields.len(), FieldSet::LITE.len() - 2);
+        assert_eq!(
+            FieldSet::LITE & FieldSet::DEFAULT,
+            FieldSet::of(&[&BROWSER, &PLATFORM, &DEVICE_TYPE])
+        );
+        assert_eq!(FieldSet::LITE - FieldSet::FULL, FieldSet::EMPTY);
+        assert!(FieldSet::EMPTY.is_empty());
+
+        let collected: FieldSet = [&PLATFORM_VERSION, &PLATFORM_VERSION, &BROWSER]
+            .into_iter()
+            .collect();
+        assert_eq!(collected, FieldSet::from(vec![&BROWSER, &PLATFORM_VERSION]));
+        assert_eq!(
+            format!("{collected:?}"),
+            "{\"BROWSER\", \"PLATFORM_VERSION\"}"
+        );
+    }
+}
@@ -1,88 +1,304 @@
 use crate::capabilities::CapaCache;
 use crate::error::ParseError;
-use crate::literal::LITERAL_CACHE;
+use crate::literal::LiteralArena;
 use crate::rule::Rule;
-use crate::{ BrowsCapField, UserAgentParser, capabilities};
-use csv::{ReaderBuilder, StringRecord};
-use hashbrown::HashSet;
+use crate::{
+    BrowsCapField, LoadPhase, ParserOptions, RuleOverlay, RulePredicate, UserAgentParser,
+    capabilities,
+};
+use crate::ini_file::IniFile;
+use crate::load_report::LoadContext;
+use csv::{Position, ReaderBuilder, StringRecord};
+use hashbrown::{Equivalent, HashMap, HashSet};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use std::io;
-use std::rc::Rc;
-use ustr::Ustr;
+use std::ops::Range;
+
+//开启parallel时每次并行整理的记录数
+#[cfg(feature = "parallel")]
+const CHUNK_SIZE: usize = 4096;
 
 pub struct FileParser {
     fields: Vec<&'static BrowsCapField>,
     rules: Vec<Rule>,
     capa_cache: CapaCache,
+    literals: LiteralArena,
+    overlay: RuleOverlay,
+    rule_filter: Option<RuleFilter>,
+}
+
+/*
+    按capabilities过滤browscap的rule，每组不同的值只判断一次。
+    在登记到CapaCache之前判断，被过滤掉的rule不占用capabilities行和字典中的字符串
+*/
+struct RuleFilter {
+    predicates: Vec<Box<RulePredicate>>,
+    unknown_value: Box<str>,
+    //判断过的值及其rule是否保留
+    kept: HashMap<Box<[Box<str>]>, bool>,
+}
+
+//用&[&str]查找以Box<[Box<str>]>为key的表，不必先复制出key；哈希与切片的哈希相同
+#[derive(Hash)]
+struct Values<'a>(&'a [&'a str]);
+
+impl Equivalent<Box<[Box<str>]>> for Values<'_> {
+    fn equivalent(&self, key: &Box<[Box<str>]>) -> bool {
+        self.0.len() == key.len() && self.0.iter().zip(key.iter()).all(|(a, b)| *a == &**b)
+    }
+}
+
+impl RuleFilter {
+    fn keeps(&mut self, fields: &[&'static BrowsCapField], values: &[&str]) -> bool {
+        if let Some(kept) = self.kept.get(&Values(values)) {
+            return *kept;
+        }
+        let capabilities = capabilities::single_row(fields.to_vec(), &self.unknown_value, |cache| {
+            cache.get_or_insert(values)
+        });
+        let kept = self
+            .predicates
+            .iter()
+            .all(|predicate| predicate.test(&capabilities));
+        self.kept.insert(values.iter().map(|value| (*value).into()).collect(), kept);
+        kept
+    }
+}
+
+//一条记录的处理结果
+enum Record {
+    Rule(Rule),
+    Suppressed,
+    Filtered,
 }
 
 impl FileParser {
-    pub fn new(unique_fields: HashSet<&'static BrowsCapField>) -> Self {
-        let fields: Vec<&'static BrowsCapField> = unique_fields.into_iter().collect();
-        crate::mapper::init_mapper(&fields);
-        crate::capabilities::init_default_capa(&fields);
+    pub fn new(unique_fields: HashSet<&'static BrowsCapField>, overlay: RuleOverlay) -> Self {
+        // 按ordinal排列，Capabilities中每个值的位置与HashSet的迭代顺序无关
+        let mut fields: Vec<&'static BrowsCapField> = unique_fields.into_iter().collect();
+        fields.sort_by_key(|field| field.ordinal());
         FileParser {
+            capa_cache: CapaCache::new(fields.clone()),
             fields,
             rules: Vec::new(),
-            capa_cache: CapaCache::new(),
+            literals: LiteralArena::new(),
+            overlay,
+            rule_filter: None,
         }
     }
 
-    pub fn parse(&mut self, read: impl io::Read) {
+    //只保留predicates都接受的browscap rule，预览的capabilities使用unknown_value作为占位符
+    pub fn set_rule_filter(&mut self, predicates: Vec<Box<RulePredicate>>, unknown_value: &str) {
+        self.rule_filter = Some(RuleFilter {
+            predicates,
+            unknown_value: unknown_value.into(),
+            kept: HashMap::new(),
+        });
+    }
+
+    pub fn parse(
+        &mut self,
+        read: impl io::Read,
+        context: &mut LoadContext,
+    ) -> Result<(), ParseError> {
+        context.enter(LoadPhase::Reading)?;
         let csv_reader = ReaderBuilder::default().has_headers(true).from_reader(read);
-        for record_r in csv_reader.into_records() {
-            if let Ok(record) = record_r {
-                let rule_result = self.get_rule(record);
-                match rule_result {
-                    Ok(rule) => {
-                        self.rules.push(rule);
+        #[cfg(not(feature = "parallel"))]
+        for item in csv_reader.into_records() {
+            let prepared = prepare_record(&item, &self.fields);
+            self.add_record(&item, prepared, context)?;
+        }
+        #[cfg(feature = "parallel")]
+        self.parse_chunks(csv_reader.into_records(), context)?;
+        context.report.rules_created = self.rules.len();
+        self.rules.shrink_to_fit();
+        Ok(())
+    }
+
+    //读取browscap的INI文件，每节沿Parent展开后按CSV记录处理
+    pub fn parse_ini(&mut self, text: &str, context: &mut LoadContext) -> Result<(), ParseError> {
+        context.enter(LoadPhase::Reading)?;
+        let ini = IniFile::new(text);
+        let fields = self.fields.clone();
+        for record in ini.records(&fields) {
+            self.add_values(record.prepared, record.line, record.byte, context)?;
+        }
+        context.report.rules_created = self.rules.len();
+        self.rules.shrink_to_fit();
+        Ok(())
+    }
+
+    /*
+        每块记录先并行整理pattern和字段值，再按记录顺序依次登记capabilities和literal，
+        这样编号和单线程解析完全一致
+    */
+    #[cfg(feature = "parallel")]
+    fn parse_chunks(
+        &mut self,
+        mut records: impl Iterator<Item = csv::Result<StringRecord>>,
+        context: &mut LoadContext,
+    ) -> Result<(), ParseError> {
+        loop {
+            let chunk: Vec<_> = records.by_ref().take(CHUNK_SIZE).collect();
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            let prepared: Vec<_> = chunk
+                .par_iter()
+                .map(|item| prepare_record(item, &self.fields))
+                .collect();
+            for (item, prepared) in chunk.iter().zip(prepared) {
+                self.add_record(item, prepared, context)?;
+            }
+        }
+    }
+
+    fn add_record(
+        &mut self,
+        item: &csv::Result<StringRecord>,
+        prepared: Result<(String, Vec<&str>), ParseError>,
+        context: &mut LoadContext,
+    ) -> Result<(), ParseError> {
+        let position = match item {
+            Ok(record) => record.position(),
+            Err(error) => error.position(),
+        };
+        let line = position.map_or(0, Position::line);
+        self.add_values(prepared, line, position.map_or(0, Position::byte), context)
+    }
+
+    fn add_values(
+        &mut self,
+        prepared: Result<(String, Vec<&str>), ParseError>,
+        line: u64,
+        byte: u64,
+        context: &mut LoadContext,
+    ) -> Result<(), ParseError> {
+        let result = prepared.and_then(|(pattern, mut values)| {
+            match self.overlay.override_for(&pattern) {
+                Some(None) => return Ok(Record::Suppressed),
+                Some(Some(replaced)) => {
+                    for (field, value) in replaced {
+                        if let Some(i) = self.fields.iter().position(|loaded| loaded == field) {
+                            values[i] = value.trim();
+                        }
                     }
-                    Err(_) => {}
                 }
+                None => {}
+            }
+            if let Some(filter) = &mut self.rule_filter
+                && !filter.keeps(&self.fields, &values)
+            {
+                return Ok(Record::Filtered);
             }
+            let capabilities = self.capa_cache.get_or_insert(&values);
+            crate::rule::create_rule(pattern, capabilities, &mut self.literals).map(Record::Rule)
+        });
+        match result {
+            Ok(Record::Rule(rule)) => self.rules.push(rule),
+            Ok(Record::Suppressed) => context.report.suppressed += 1,
+            Ok(Record::Filtered) => context.report.filtered += 1,
+            Err(reason) => context.skip(line, reason),
         }
-        self.rules.push(crate::rule::get_wild_card_rule(&self.fields));
-        self.rules.shrink_to_fit();
-        LITERAL_CACHE.clear();
+        context.record_read(byte)
     }
 
-    pub fn get_rule(&self, record: StringRecord) -> Result<Rule, ParseError> {
-        if record.len() <= 47 {
-            return Err(ParseError::InvalidRecord);
-        }
-        if let Some(rule_str_column) = record.get(0) {
-            let pattern = crate::rule::normalize_pattern(rule_str_column);
-            let values = get_brows_cap_fields(&record, &self.fields);
-            let capabilities = crate::capabilities::get_capabilities(values, &self.capa_cache);
-            let rule = crate::rule::create_rule(pattern, capabilities);
-            Ok(rule?)
-        } else {
-            Err(ParseError::InvalidRecord)
+    //把overlay中的rule按优先级放到browscap的rule前后，返回Before、Ordered、After各自的范围
+    fn add_custom_rules(&mut self, context: &mut LoadContext) -> Result<[Range<usize>; 3], ParseError> {
+        let mut tiers: [Vec<Rule>; 3] = Default::default();
+        for custom in self.overlay.rules() {
+            let capabilities = self.capa_cache.get_custom(&custom.values);
+            let pattern = crate::rule::normalize_pattern(&custom.pattern);
+            let rule = crate::rule::create_rule(pattern, capabilities, &mut self.literals)
+                .map_err(|reason| ParseError::Overlay(format!("pattern `{}`: {reason}", custom.pattern)))?;
+            tiers[custom.priority as usize].push(rule);
         }
+        context.report.custom_rules = self.overlay.rules().len();
+        let [before, ordered, after] = tiers;
+        let browscap = std::mem::replace(&mut self.rules, before);
+        let ordered_start = self.rules.len();
+        self.rules.extend(browscap);
+        self.rules.extend(ordered);
+        let after_start = self.rules.len();
+        self.rules.extend(after);
+        Ok([0..ordered_start, ordered_start..after_start, after_start..self.rules.len()])
     }
 }
 
-pub fn create_agent_parser(file_parser: FileParser) -> UserAgentParser {
-    UserAgentParser::new(file_parser.rules)
+pub fn create_agent_parser(
+    mut file_parser: FileParser,
+    options: &ParserOptions,
+    context: &mut LoadContext,
+) -> Result<UserAgentParser, ParseError> {
+    let tiers = file_parser.add_custom_rules(context)?;
+    UserAgentParser::create(
+        file_parser.rules,
+        tiers,
+        file_parser.literals,
+        file_parser.capa_cache.finish(options),
+        options,
+        context,
+    )
+}
+
+//只读取记录本身，不涉及共享的缓存
+fn prepare_record<'r>(
+    item: &'r csv::Result<StringRecord>,
+    fields: &[&'static BrowsCapField],
+) -> Result<(String, Vec<&'r str>), ParseError> {
+    let record = item.as_ref().map_err(|_| ParseError::InvalidRecord)?;
+    if record.len() <= 47 {
+        return Err(ParseError::InvalidRecord);
+    }
+    let rule_str_column = record.get(0).ok_or(ParseError::InvalidRecord)?;
+    let pattern = crate::rule::normalize_pattern(rule_str_column);
+    Ok((pattern, get_brows_cap_fields(record, fields)))
 }
 
 //合并了get_value方法
-fn get_brows_cap_fields<'b>(
-    record: &StringRecord,
-    fields: &Vec<&'static BrowsCapField>,
-) -> Vec<Ustr> {
-    let mut values: Vec<Ustr> = Vec::new();
-    for field in fields.iter() {
-        let value = record.get(field.index());
-        if value.is_none() {
-            values.push(Ustr::from(capabilities::UNKNOWN_BROWSCAP_VALUE));
-            continue;
+fn get_brows_cap_fields<'r>(
+    record: &'r StringRecord,
+    fields: &[&'static BrowsCapField],
+) -> Vec<&'r str> {
+    fields
+        .iter()
+        .map(|field| match record.get(field.index()).map(str::trim) {
+            Some(value) if !value.is_empty() => value,
+            _ => capabilities::MISSING_VALUE,
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod test_file_parser {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_chunks_same_as_sequential() {
+        let fields = crate::merge_fields(crate::FieldSet::FULL);
+        let mut chunked = FileParser::new(fields.clone(), RuleOverlay::default());
+        let mut context = LoadContext::new(None, None);
+        chunked.parse(File::open(crate::DEFAULT_FILE_NAME).unwrap(), &mut context).unwrap();
+
+        let mut sequential = FileParser::new(fields, RuleOverlay::default());
+        let csv_reader = ReaderBuilder::default()
+            .has_headers(true)
+            .from_reader(File::open(crate::DEFAULT_FILE_NAME).unwrap());
+        let mut sequential_context = LoadContext::new(None, None);
+        for item in csv_reader.into_records() {
+            let prepared = prepare_record(&item, &sequential.fields);
+            sequential.add_record(&item, prepared, &mut sequential_context).unwrap();
         }
-        let trimmed = Rc::new(value.unwrap().trim().to_string());
-        if trimmed.is_empty() {
-            values.push(Ustr::from(capabilities::UNKNOWN_BROWSCAP_VALUE));
-            continue;
+
+        assert_eq!(chunked.rules.len(), sequential.rules.len());
+        for (left, right) in chunked.rules.iter().zip(&sequential.rules) {
+            assert_eq!(left.get_pattern(), right.get_pattern());
+            assert_eq!(left.get_capabilities(), right.get_capabilities());
         }
-        values.push(Ustr::from(&trimmed));
+        assert_eq!(chunked.literals.len(), sequential.literals.len());
+        assert_eq!(context.report.records_read, sequential_context.report.records_read);
+        assert_eq!(context.report.skipped.len(), sequential_context.report.skipped.len());
     }
-    values
 }
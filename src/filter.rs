@@ -1,53 +1,329 @@
 use crate::literal::Literal;
 use crate::rule::Rule;
 use crate::searchable_string::{self, SearchableString};
+use aho_corasick::AhoCorasick;
 use bitvec::prelude::BitVec;
+use hashbrown::{HashMap, HashSet};
+use roaring::RoaringBitmap;
 use std::fmt::Debug;
-use std::ops::Not;
 use ustr::Ustr;
 
-#[derive(Debug)]
+//最多生成的contains过滤器个数
+const MAX_CONTAINS_FILTERS: usize = 96;
+//最多生成的prefix过滤器个数
+const MAX_PREFIX_FILTERS: usize = 2;
+//至少被这么多条rule依赖的token才值得生成过滤器
+const MIN_RULES_PER_FILTER: usize = 2;
+const MIN_TOKEN_LEN: usize = 2;
+//置位不超过总位数的1/SPARSE_DENSITY的掩码压缩存储
+const SPARSE_DENSITY: usize = 64;
+
+#[derive(Debug, Clone)]
 pub enum FilterType {
-    Prefix(Literal),
-    Contains(Literal),
+    Prefix(Box<Literal>),
+    Contains(Ustr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Filter {
     filter_type: FilterType,
-    my_mask: BitVec,
+    my_mask: Mask,
 }
 impl Filter {
-    pub(crate) fn new(filter_type: FilterType, my_mask: BitVec) -> Filter {
-        let filter = Filter {
+    pub(crate) fn new(filter_type: FilterType, my_mask: Mask) -> Filter {
+        Filter {
             filter_type,
             my_mask,
-        };
-        filter
+        }
     }
 }
 
+/*
+    过滤器的rule掩码：置位多的用bitvec，按字做或运算；
+    置位少的用Roaring bitmap，占用更少的内存。置位少的掩码对应rule中很少用到的token，
+    UA中缺少的token要或上它的掩码，所以压缩的contains掩码合起来处理，见SparseUnion
+*/
+#[derive(Debug, Clone)]
+pub enum Mask {
+    Dense(BitVec),
+    Compressed(RoaringBitmap),
+}
+
+impl Mask {
+    //compress为true时按每个掩码的密度选择：置位不超过1/SPARSE_DENSITY的用Roaring bitmap
+    pub(crate) fn new(mask: BitVec, compress: bool) -> Mask {
+        if compress && mask.count_ones() * SPARSE_DENSITY <= mask.len() {
+            let bitmap = RoaringBitmap::from_sorted_iter(mask.iter_ones().map(|i| i as u32)).unwrap();
+            if bitmap.serialized_size() < size_of_val(mask.as_raw_slice()) {
+                return Mask::Compressed(bitmap);
+            }
+        }
+        Mask::Dense(mask)
+    }
+
+    fn or_into(&self, target: &mut BitVec) {
+        match self {
+            Mask::Dense(mask) => or_assign(target, mask),
+            Mask::Compressed(bitmap) => or_bitmap(target, bitmap),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        match self {
+            Mask::Dense(mask) => size_of_val(mask.as_raw_slice()),
+            Mask::Compressed(bitmap) => bitmap.serialized_size(),
+        }
+    }
+
+    //在position插入一位，其后的位依次后移
+    fn insert(&mut self, position: usize, bit: bool) {
+        match self {
+            Mask::Dense(mask) => mask.insert(position, bit),
+            Mask::Compressed(bitmap) => {
+                shift_bitmap(bitmap, position as u32, true);
+                if bit {
+                    bitmap.insert(position as u32);
+                }
+            }
+        }
+    }
+
+    //去掉position处的位，其后的位依次前移
+    fn remove(&mut self, position: usize) {
+        match self {
+            Mask::Dense(mask) => {
+                mask.remove(position);
+            }
+            Mask::Compressed(bitmap) => shift_bitmap(bitmap, position as u32, false),
+        }
+    }
+}
+
+/*
+    所有过滤器。contains过滤器的token按下标编入同一个Aho-Corasick自动机，
+    一次扫描UA即可得到所有出现过的token
+*/
+#[derive(Debug, Clone)]
+pub struct FilterSet {
+    my_prefix_filters: Vec<Filter>,
+    my_contains_filters: Vec<Filter>,
+    my_automaton: AhoCorasick,
+    my_sparse: Box<SparseUnion>,
+}
+
+/*
+    压缩的contains掩码的并集。解析时先取整个并集，再去掉只被UA中出现了的token覆盖的位，
+    这样只需展开出现了的token的掩码，不必逐位或上每个缺少的token的Roaring bitmap
+*/
+#[derive(Debug, Clone, PartialEq)]
+struct SparseUnion {
+    my_union: BitVec,
+    //被两个及以上压缩掩码覆盖的位
+    my_shared: RoaringBitmap,
+    //按在my_shared中的序号，覆盖该位的token在my_cover_tokens中的起止位置
+    my_cover_offsets: Vec<u32>,
+    my_cover_tokens: Vec<u32>,
+}
+
+impl SparseUnion {
+    fn new(filters: &[Filter], rules_len: usize) -> SparseUnion {
+        let bitmaps: Vec<(usize, &RoaringBitmap)> = filters
+            .iter()
+            .enumerate()
+            .filter_map(|(token, filter)| match &filter.my_mask {
+                Mask::Compressed(bitmap) => Some((token, bitmap)),
+                Mask::Dense(_) => None,
+            })
+            .collect();
+        let mut union = RoaringBitmap::new();
+        let mut my_shared = RoaringBitmap::new();
+        for (_, bitmap) in bitmaps.iter() {
+            my_shared |= &union & *bitmap;
+            union |= *bitmap;
+        }
+        let mut my_cover_offsets = vec![0];
+        let mut my_cover_tokens = Vec::new();
+        for i in my_shared.iter() {
+            my_cover_tokens.extend(
+                bitmaps
+                    .iter()
+                    .filter(|(_, bitmap)| bitmap.contains(i))
+                    .map(|(token, _)| *token as u32),
+            );
+            my_cover_offsets.push(my_cover_tokens.len() as u32);
+        }
+        let mut my_union = BitVec::repeat(false, rules_len);
+        or_bitmap(&mut my_union, &union);
+        SparseUnion {
+            my_union,
+            my_shared,
+            my_cover_offsets,
+            my_cover_tokens,
+        }
+    }
+
+    //覆盖共享位i的token，i必须在my_shared中
+    fn covering_tokens(&self, i: u32) -> &[u32] {
+        let rank = self.my_shared.rank(i) as usize;
+        let start = self.my_cover_offsets[rank - 1] as usize;
+        let end = self.my_cover_offsets[rank] as usize;
+        &self.my_cover_tokens[start..end]
+    }
+
+    //在position插入一条rule，covering是掩码中有这条rule的压缩token，就地更新
+    fn insert_rule(&mut self, position: usize, covering: &[u32]) {
+        self.my_union.insert(position, !covering.is_empty());
+        shift_bitmap(&mut self.my_shared, position as u32, true);
+        if covering.len() > 1 {
+            self.my_shared.insert(position as u32);
+            let rank = self.my_shared.rank(position as u32) as usize - 1;
+            let start = self.my_cover_offsets[rank];
+            let len = covering.len() as u32;
+            self.my_cover_tokens
+                .splice(start as usize..start as usize, covering.iter().copied());
+            self.my_cover_offsets[rank + 1..]
+                .iter_mut()
+                .for_each(|offset| *offset += len);
+            self.my_cover_offsets.insert(rank + 1, start + len);
+        }
+    }
+
+    fn remove_rule(&mut self, position: usize) {
+        if self.my_shared.contains(position as u32) {
+            let rank = self.my_shared.rank(position as u32) as usize - 1;
+            let start = self.my_cover_offsets[rank];
+            let end = self.my_cover_offsets[rank + 1];
+            self.my_cover_tokens.drain(start as usize..end as usize);
+            self.my_cover_offsets.remove(rank + 1);
+            self.my_cover_offsets[rank + 1..]
+                .iter_mut()
+                .for_each(|offset| *offset -= end - start);
+        }
+        shift_bitmap(&mut self.my_shared, position as u32, false);
+        self.my_union.remove(position);
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of_val(self.my_union.as_raw_slice())
+            + self.my_shared.serialized_size()
+            + size_of_val(self.my_cover_offsets.as_slice())
+            + size_of_val(self.my_cover_tokens.as_slice())
+    }
+}
+
+impl FilterSet {
+    pub(crate) fn new(
+        prefix_filters: Vec<Filter>,
+        contains_filters: Vec<Filter>,
+        rules_len: usize,
+    ) -> FilterSet {
+        let tokens = contains_filters.iter().map(|filter| match &filter.filter_type {
+            FilterType::Contains(token) => token.as_str(),
+            FilterType::Prefix(_) => unreachable!("prefix filter in contains filters"),
+        });
+        FilterSet {
+            my_automaton: AhoCorasick::new(tokens).unwrap(),
+            my_sparse: Box::new(SparseUnion::new(&contains_filters, rules_len)),
+            my_prefix_filters: prefix_filters,
+            my_contains_filters: contains_filters,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.my_prefix_filters.len() + self.my_contains_filters.len()
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        let filters = self
+            .my_prefix_filters
+            .iter()
+            .chain(self.my_contains_filters.iter())
+            .map(|filter| {
+                let literal = match &filter.filter_type {
+                    FilterType::Prefix(_) => size_of::<Literal>(),
+                    FilterType::Contains(_) => 0,
+                };
+                size_of::<Filter>() + literal + filter.my_mask.memory_usage()
+            })
+            .sum::<usize>();
+        filters + self.my_automaton.memory_usage() + self.my_sparse.memory_usage()
+    }
+
+    /*
+        在position插入一条rule，更新每个过滤器的掩码。过滤器的token不重新挑选：
+        新rule不依赖任何token时不会被过滤掉，结果与重新构建相同，只是少过滤一些
+    */
+    pub(crate) fn insert_rule(&mut self, position: usize, rule: &Rule) {
+        for filter in self
+            .my_prefix_filters
+            .iter_mut()
+            .chain(self.my_contains_filters.iter_mut())
+        {
+            let bit = match &filter.filter_type {
+                FilterType::Prefix(literal) => has_prefix(rule, literal.get_string()),
+                FilterType::Contains(token) => rule.requires(*token),
+            };
+            filter.my_mask.insert(position, bit);
+        }
+        let covering: Vec<u32> = self
+            .my_contains_filters
+            .iter()
+            .enumerate()
+            .filter(|(_, filter)| {
+                matches!(&filter.my_mask, Mask::Compressed(bitmap) if bitmap.contains(position as u32))
+            })
+            .map(|(token, _)| token as u32)
+            .collect();
+        self.my_sparse.insert_rule(position, &covering);
+    }
+
+    pub(crate) fn remove_rule(&mut self, position: usize) {
+        for filter in self
+            .my_prefix_filters
+            .iter_mut()
+            .chain(self.my_contains_filters.iter_mut())
+        {
+            filter.my_mask.remove(position);
+        }
+        self.my_sparse.remove_rule(position);
+    }
+
+    //过滤器token在Ustr全局表中占用的字节数
+    pub(crate) fn string_bytes(&self) -> usize {
+        self.my_prefix_filters
+            .iter()
+            .chain(self.my_contains_filters.iter())
+            .map(|filter| match &filter.filter_type {
+                FilterType::Prefix(literal) => literal.get_length(),
+                FilterType::Contains(token) => token.len(),
+            })
+            .sum()
+    }
+}
 
 /*
     用长度为rule个数的bitvec，用每一个位保存当前prefix与所有逐个rule的prefix比较“起始于”的真假结果
     方法结果是返回一个记录prefix filter的bitvec数据，记录着与每个rule的前缀匹配情况
 */
-pub(crate) fn create_prefix_masker(rules: &Vec<Rule>, pattern: Ustr) -> BitVec {
+pub(crate) fn create_prefix_masker(rules: &[Rule], pattern: Ustr) -> BitVec {
     let mut my_mask = BitVec::new();
     my_mask.resize(rules.len(), false);
     for (i, rule) in rules.iter().enumerate() {
-        let r = match rule.get_prefix() {
-            Some(prefix) => prefix.get_string().starts_with(pattern.as_str()),
-            None => false,
-        };
-        if r {
+        if has_prefix(rule, pattern) {
             my_mask.set(i, true);
         }
     }
     my_mask
 }
 
-pub fn create_contains_masker(rules: &Vec<Rule>, pattern: Ustr) -> BitVec {
+fn has_prefix(rule: &Rule, pattern: Ustr) -> bool {
+    match rule.get_prefix() {
+        Some(prefix) => prefix.get_string().starts_with(pattern.as_str()),
+        None => false,
+    }
+}
+
+pub fn create_contains_masker(rules: &[Rule], pattern: Ustr) -> BitVec {
     let mut my_mask = BitVec::new();
     my_mask.resize(rules.len(), false);
     for (i, rule) in rules.iter().enumerate() {
@@ -59,26 +335,343 @@ pub fn create_contains_masker(rules: &Vec<Rule>, pattern: Ustr) -> BitVec {
     my_mask
 }
 
+/*
+    从已加载rule的literal中按出现频率挑选contains过滤器的token：
+    literal按?切成固定片段，再取其中的字母数字单词和单个数字，统计每个token被多少条rule依赖
+*/
+pub(crate) fn select_contains_tokens(rules: &[Rule]) -> Vec<Ustr> {
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    let mut rule_tokens: HashSet<&'static str> = HashSet::new();
+    for rule in rules {
+        rule_tokens.clear();
+        for literal in rule.get_literals() {
+            collect_tokens(literal.get_string().as_str(), &mut rule_tokens);
+        }
+        for token in rule_tokens.iter() {
+            *counts.entry(token).or_insert(0) += 1;
+        }
+    }
+    top_tokens(counts, MAX_CONTAINS_FILTERS)
+}
+
+/*
+    按出现频率挑选prefix过滤器：取rule前缀里第一个空格或?之前的部分，例如"mozilla/5.0"
+*/
+pub(crate) fn select_prefix_tokens(rules: &[Rule]) -> Vec<Ustr> {
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    for rule in rules {
+        if let Some(prefix) = rule.get_prefix() {
+            let value = prefix.get_string().as_str();
+            let end = value.find([' ', '?']).unwrap_or(value.len());
+            if end >= MIN_TOKEN_LEN {
+                *counts.entry(&value[..end]).or_insert(0) += 1;
+            }
+        }
+    }
+    top_tokens(counts, MAX_PREFIX_FILTERS)
+}
+
+fn collect_tokens(literal: &'static str, tokens: &mut HashSet<&'static str>) {
+    for segment in literal.split('?') {
+        for word in segment.split(|c: char| !c.is_ascii_alphanumeric()) {
+            if word.len() >= MIN_TOKEN_LEN {
+                tokens.insert(word);
+            }
+        }
+        for digit in segment.matches(|c: char| c.is_ascii_digit()) {
+            tokens.insert(digit);
+        }
+    }
+}
+
+//频率从高到低，频率相同时按字典序，保证结果稳定
+fn top_tokens(counts: HashMap<&'static str, usize>, limit: usize) -> Vec<Ustr> {
+    let mut sorted: Vec<(&'static str, usize)> = counts
+        .into_iter()
+        .filter(|(_, count)| *count >= MIN_RULES_PER_FILTER)
+        .collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    sorted
+        .into_iter()
+        .take(limit)
+        .map(|(token, _)| Ustr::from(token))
+        .collect()
+}
+
+//把未通过过滤器的rule对应位置为false，结果写入复用的includes，避免每次解析都分配新的bitvec
 pub(crate) fn filter(
     searchable_string: &mut SearchableString,
-    filters: &Vec<Filter>,
-    excludes_len: usize,
-) -> BitVec {
-    let mut bit_vec = BitVec::new();
-    bit_vec.resize(excludes_len, false);
-    for filter in filters.iter() {
-        match &filter.filter_type {
-            FilterType::Prefix(literal) => {
-                if !searchable_string::starts_with(searchable_string, literal) {
-                    bit_vec |= &filter.my_mask;
-                }
-            }
-            FilterType::Contains(literal) => {
-                if !(searchable_string.get_indices(literal).len() > 0) {
-                    bit_vec |= &filter.my_mask;
-                }
+    filters: &FilterSet,
+    rules_len: usize,
+    found_tokens: &mut BitVec,
+    includes: &mut BitVec,
+    sparse_excludes: &mut RoaringBitmap,
+) {
+    found_tokens.clear();
+    found_tokens.resize(filters.my_contains_filters.len(), false);
+    for found in filters
+        .my_automaton
+        .find_overlapping_iter(searchable_string.as_bytes())
+    {
+        found_tokens.set(found.pattern().as_usize(), true);
+    }
+
+    // includes先存放要排除的rule：压缩掩码的并集，去掉只被出现了的token覆盖的位
+    let sparse = &filters.my_sparse;
+    debug_assert_eq!(sparse.my_union.len(), rules_len);
+    includes.clone_from(&sparse.my_union);
+    // 出现了的token的压缩掩码先按bitmap求并，不在my_shared中的位只被出现了的token覆盖
+    sparse_excludes.clear();
+    for token in found_tokens.iter_ones() {
+        if let Mask::Compressed(bitmap) = &filters.my_contains_filters[token].my_mask {
+            *sparse_excludes |= bitmap;
+        }
+    }
+    let shared = &*sparse_excludes & &sparse.my_shared;
+    *sparse_excludes -= &sparse.my_shared;
+    and_not_bitmap(includes, sparse_excludes);
+    // 被多个掩码覆盖的位，都是出现了的token覆盖的才不排除
+    for i in shared.iter() {
+        if sparse
+            .covering_tokens(i)
+            .iter()
+            .all(|token| found_tokens[*token as usize])
+        {
+            includes.set(i as usize, false);
+        }
+    }
+
+    // 没有通过的prefix过滤器中，压缩的掩码也先求并再一次或进includes
+    sparse_excludes.clear();
+    for filter in filters.my_prefix_filters.iter() {
+        if let FilterType::Prefix(literal) = &filter.filter_type
+            && !searchable_string::starts_with(searchable_string, literal)
+        {
+            match &filter.my_mask {
+                Mask::Compressed(bitmap) => *sparse_excludes |= bitmap,
+                dense => dense.or_into(includes),
             }
         }
     }
-    bit_vec.not()
+    or_bitmap(includes, sparse_excludes);
+    for i in found_tokens.iter_zeros() {
+        if let Mask::Dense(mask) = &filters.my_contains_filters[i].my_mask {
+            or_assign(includes, mask);
+        }
+    }
+
+    let excludes = std::mem::take(includes);
+    *includes = !excludes;
+}
+
+//按机器字做或运算，bitvec自带的|=是逐位迭代的
+pub(crate) fn or_assign(target: &mut BitVec, mask: &BitVec) {
+    for (word, mask_word) in target
+        .as_raw_mut_slice()
+        .iter_mut()
+        .zip(mask.as_raw_slice())
+    {
+        *word |= *mask_word;
+    }
+}
+
+//只移动position及之后的位：insert时后移一位，否则去掉position处的位并前移一位
+fn shift_bitmap(bitmap: &mut RoaringBitmap, position: u32, insert: bool) {
+    let tail: Vec<u32> = bitmap
+        .range(position..)
+        .filter(|i| insert || *i != position)
+        .collect();
+    bitmap.remove_range(position..);
+    let shifted = tail.into_iter().map(|i| if insert { i + 1 } else { i - 1 });
+    bitmap.append(shifted).unwrap();
+}
+
+//清掉bitvec中Roaring bitmap置位的位
+fn and_not_bitmap(target: &mut BitVec, bitmap: &RoaringBitmap) {
+    let words = target.as_raw_mut_slice();
+    bitmap.iter().for_each(|i| {
+        words[(i / usize::BITS) as usize] &= !(1 << (i % usize::BITS));
+    });
+}
+
+//把Roaring bitmap的每一位或进bitvec
+fn or_bitmap(target: &mut BitVec, bitmap: &RoaringBitmap) {
+    let words = target.as_raw_mut_slice();
+    bitmap.iter().for_each(|i| {
+        words[(i / usize::BITS) as usize] |= 1 << (i % usize::BITS);
+    });
+}
+
+pub(crate) fn and_assign(target: &mut BitVec, mask: &BitVec) {
+    for (word, mask_word) in target
+        .as_raw_mut_slice()
+        .iter_mut()
+        .zip(mask.as_raw_slice())
+    {
+        *word &= *mask_word;
+    }
+}
+
+#[cfg(test)]
+mod test_filter {
+    use super::*;
+    use crate::literal::LiteralArena;
+
+    fn create_rules(patterns: &[&str]) -> Vec<Rule> {
+        let mut literals = LiteralArena::new();
+        patterns
+            .iter()
+            .map(|pattern| {
+                crate::rule::create_rule(pattern.to_string(), 0, &mut literals).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_collect_tokens() {
+        let mut tokens = HashSet::new();
+        collect_tokens("mozilla/5.0 (windows nt 6.?*", &mut tokens);
+        let mut tokens: Vec<&str> = tokens.into_iter().collect();
+        tokens.sort();
+        assert_eq!(tokens, vec!["0", "5", "6", "mozilla", "nt", "windows"]);
+    }
+
+    #[test]
+    fn test_select_tokens() {
+        let rules = create_rules(&[
+            "mozilla/5.0 (*android*chrome/*",
+            "mozilla/5.0 (*iphone*safari/*",
+            "mozilla/4.0 (*android*",
+            "*googlebot*",
+        ]);
+        let contains = select_contains_tokens(&rules);
+        assert_eq!(contains[..2], [Ustr::from("0"), Ustr::from("mozilla")]);
+        assert!(contains.contains(&Ustr::from("android")));
+        assert!(!contains.contains(&Ustr::from("googlebot")));
+
+        let prefixes = select_prefix_tokens(&rules);
+        assert_eq!(prefixes, vec![Ustr::from("mozilla/5.0")]);
+    }
+
+    #[test]
+    fn test_filter_excludes_missing_tokens() {
+        let rules = create_rules(&["*android*chrome/*", "*iphone*", "*android*"]);
+        let contains = vec![
+            Filter::new(
+                FilterType::Contains(Ustr::from("android")),
+                Mask::new(create_contains_masker(&rules, Ustr::from("android")), false),
+            ),
+            Filter::new(
+                FilterType::Contains(Ustr::from("chrome")),
+                Mask::new(create_contains_masker(&rules, Ustr::from("chrome")), true),
+            ),
+        ];
+        let filters = FilterSet::new(Vec::new(), contains, rules.len());
+        let mut search = SearchableString::new("Mozilla/5.0 (Linux; Android 9) Firefox/60");
+        let mut found = BitVec::new();
+        let mut includes = BitVec::new();
+        let mut sparse = RoaringBitmap::new();
+        filter(&mut search, &filters, rules.len(), &mut found, &mut includes, &mut sparse);
+        assert_eq!(includes.iter_ones().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_sparse_masks() {
+        let mut android = BitVec::repeat(false, 1000);
+        android[..500].fill(true);
+        let sparse = |bits: &[usize]| {
+            let mut mask = BitVec::repeat(false, 1000);
+            bits.iter().for_each(|i| mask.set(*i, true));
+            Mask::new(mask, true)
+        };
+        let contains = vec![
+            Filter::new(FilterType::Contains(Ustr::from("android")), Mask::new(android, true)),
+            Filter::new(FilterType::Contains(Ustr::from("chrome")), sparse(&[503, 600])),
+            Filter::new(FilterType::Contains(Ustr::from("iphone")), sparse(&[600, 700])),
+        ];
+        assert!(matches!(contains[0].my_mask, Mask::Dense(_)));
+        assert!(matches!(contains[1].my_mask, Mask::Compressed(_)));
+        let filters = FilterSet::new(Vec::new(), contains, 1000);
+        let mut found = BitVec::new();
+        let mut includes = BitVec::new();
+        let mut sparse_excludes = RoaringBitmap::new();
+        // 没有压缩的token出现时使用并集，出现时只排除缺少的token
+        for (user_agent, excludes) in [
+            ("Mozilla/5.0 (Linux; Android 9) Firefox/60", vec![503, 600, 700]),
+            ("Mozilla/5.0 (iPhone; CPU OS 12) Safari/604", vec![503, 600]),
+            ("Mozilla/5.0 (iPhone; CPU OS 12) Chrome/70", vec![]),
+        ] {
+            let mut search = SearchableString::new(user_agent);
+            filter(&mut search, &filters, 1000, &mut found, &mut includes, &mut sparse_excludes);
+            let excluded: Vec<usize> = includes.iter_zeros().filter(|i| *i >= 500).collect();
+            assert_eq!(excluded, excludes, "{user_agent}");
+            assert_eq!(includes[..500].not_any(), !user_agent.contains("Android"));
+        }
+    }
+
+    #[test]
+    fn test_update_sparse_union() {
+        let sparse = |bits: &[usize]| {
+            let mut mask = BitVec::repeat(false, 1000);
+            bits.iter().for_each(|i| mask.set(*i, true));
+            Mask::new(mask, true)
+        };
+        let contains = vec![
+            Filter::new(FilterType::Contains(Ustr::from("chrome")), sparse(&[3, 503, 600])),
+            Filter::new(FilterType::Contains(Ustr::from("iphone")), sparse(&[600, 700])),
+            Filter::new(FilterType::Contains(Ustr::from("ipad")), sparse(&[600, 800])),
+        ];
+        let mut filters = FilterSet::new(Vec::new(), contains, 1000);
+        let rules = create_rules(&["*chrome*iphone*", "*ipad*", "x*"]);
+        // 就地更新的结果与重新构建相同
+        for (position, rule) in [550, 0, 999].into_iter().zip(&rules) {
+            filters.insert_rule(position, rule);
+            let rules_len = filters.my_sparse.my_union.len();
+            assert_eq!(*filters.my_sparse, SparseUnion::new(&filters.my_contains_filters, rules_len));
+        }
+        for position in [601, 551, 0, 0] {
+            filters.remove_rule(position);
+            let rules_len = filters.my_sparse.my_union.len();
+            assert_eq!(*filters.my_sparse, SparseUnion::new(&filters.my_contains_filters, rules_len));
+        }
+        assert_eq!(filters.my_sparse.my_union.count_ones(), 5);
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut sparse = BitVec::repeat(false, 1000);
+        sparse.set(3, true);
+        sparse.set(64, true);
+        sparse.set(999, true);
+        let compressed = Mask::new(sparse.clone(), true);
+        assert!(matches!(compressed, Mask::Compressed(_)));
+        assert!(matches!(Mask::new(sparse.clone(), false), Mask::Dense(_)));
+        assert!(matches!(Mask::new(BitVec::repeat(true, 1000), true), Mask::Dense(_)));
+
+        let mut target = BitVec::repeat(false, 1000);
+        target.set(5, true);
+        compressed.or_into(&mut target);
+        assert_eq!(target.iter_ones().collect::<Vec<_>>(), vec![3, 5, 64, 999]);
+    }
+
+    #[test]
+    fn test_mask_insert_remove() {
+        let mut bits = BitVec::repeat(false, 1000);
+        bits.set(3, true);
+        bits.set(64, true);
+        for compress in [false, true] {
+            let mut mask = Mask::new(bits.clone(), compress);
+            mask.insert(10, true);
+            mask.insert(0, false);
+            let mut target = BitVec::repeat(false, 1002);
+            mask.or_into(&mut target);
+            assert_eq!(target.iter_ones().collect::<Vec<_>>(), vec![4, 11, 66]);
+
+            mask.remove(4);
+            mask.remove(0);
+            let mut target = BitVec::repeat(false, 1000);
+            mask.or_into(&mut target);
+            assert_eq!(target.iter_ones().collect::<Vec<_>>(), vec![9, 64]);
+        }
+    }
 }
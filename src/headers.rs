@@ -0,0 +1,174 @@
+use crate::{
+    BrowsCapField, ClientHints, DEVICE_BRAND_NAME, DEVICE_CODE_NAME, DEVICE_MAKER, DEVICE_NAME,
+    DEVICE_POINTING_METHOD, DEVICE_TYPE, FieldValue, HintedCapabilities, IS_MOBILE_DEVICE,
+    IS_TABLET, PLATFORM, PLATFORM_BITS, PLATFORM_DESCRIPTION, PLATFORM_MAKER, PLATFORM_VERSION,
+    Provenance, UserAgentParser,
+};
+
+//代理和运营商转发设备原始UA时使用的header，按优先级排列
+const DEVICE_UA_HEADERS: [&str; 4] = [
+    "x-operamini-phone-ua",
+    "x-device-user-agent",
+    "x-original-user-agent",
+    "device-stock-ua",
+];
+
+//取自设备原始UA的字段，其余字段来自User-Agent
+const DEVICE_FIELDS: [&BrowsCapField; 13] = [
+    &PLATFORM,
+    &PLATFORM_VERSION,
+    &PLATFORM_DESCRIPTION,
+    &PLATFORM_BITS,
+    &PLATFORM_MAKER,
+    &IS_MOBILE_DEVICE,
+    &IS_TABLET,
+    &DEVICE_NAME,
+    &DEVICE_MAKER,
+    &DEVICE_TYPE,
+    &DEVICE_POINTING_METHOD,
+    &DEVICE_CODE_NAME,
+    &DEVICE_BRAND_NAME,
+];
+
+impl UserAgentParser {
+    /// Parses a request from its headers, whose names are matched case-insensitively:
+    ///
+    /// * `User-Agent` is matched for the browser
+    /// * the first of `X-OperaMini-Phone-UA`, `X-Device-User-Agent`, `X-Original-User-Agent`
+    ///   and `Device-Stock-UA`, in which proxies and carriers forward the device's own user
+    ///   agent, is matched for the platform and device fields, which replace those from
+    ///   `User-Agent` when they have a value. Without a `User-Agent` it is used for every field,
+    ///   and every value has [`Provenance::DeviceUserAgent`]
+    /// * the client hints are laid over last, see [`ClientHints::apply`]
+    ///
+    /// When a header occurs more than once, its first value is used.
+    pub fn parse_headers<'h>(
+        &self,
+        headers: impl IntoIterator<Item = (&'h str, &'h str)>,
+    ) -> HintedCapabilities<'_> {
+        let mut user_agent = None;
+        let mut device_user_agents = [None; DEVICE_UA_HEADERS.len()];
+        let mut other_headers = Vec::new();
+        for (name, value) in headers {
+            let lower_name = name.to_ascii_lowercase();
+            if lower_name == "user-agent" {
+                user_agent.get_or_insert(value);
+            } else if let Some(i) = DEVICE_UA_HEADERS.iter().position(|header| *header == lower_name) {
+                device_user_agents[i].get_or_insert(value);
+            } else {
+                other_headers.push((name, value));
+            }
+        }
+        let user_agent = user_agent.filter(|user_agent| !user_agent.is_empty());
+        let device_user_agent = device_user_agents
+            .into_iter()
+            .flatten()
+            .find(|user_agent| !user_agent.is_empty());
+
+        let mut hinted = match (user_agent, device_user_agent) {
+            (Some(user_agent), Some(device_user_agent)) => {
+                let outcome = self.parse_outcome(user_agent);
+                let mut hinted = HintedCapabilities::new(outcome, Provenance::UserAgent);
+                let device = self.parse_outcome(device_user_agent);
+                if device.fallback.is_none() {
+                    for field in DEVICE_FIELDS {
+                        if let FieldValue::Value(value) = device.capabilities.get_field(field) {
+                            hinted.set(field, value.to_string(), Provenance::DeviceUserAgent);
+                        }
+                    }
+                }
+                hinted
+            }
+            (Some(user_agent), None) => {
+                HintedCapabilities::new(self.parse_outcome(user_agent), Provenance::UserAgent)
+            }
+            (None, Some(user_agent)) => {
+                let outcome = self.parse_outcome(user_agent);
+                HintedCapabilities::new(outcome, Provenance::DeviceUserAgent)
+            }
+            (None, None) => HintedCapabilities::new(self.parse_outcome(""), Provenance::UserAgent),
+        };
+        ClientHints::from_headers(other_headers).overlay(&mut hinted);
+        hinted
+    }
+
+    /// Like [`UserAgentParser::parse_headers`]. Header values that are not visible ASCII are
+    /// ignored.
+    #[cfg(feature = "http")]
+    pub fn parse_header_map(&self, headers: &http::HeaderMap) -> HintedCapabilities<'_> {
+        self.parse_headers(
+            headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_headers {
+    use super::*;
+    use crate::{BROWSER, Fallback, FieldSet};
+
+    // 测试数据中没有Opera Mini，用桌面浏览器代替代理的User-Agent
+    const PROXY: &str = "Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/56.0.2924.87 Safari/537.36";
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+
+    fn create_parser() -> UserAgentParser {
+        UserAgentParser::builder().fields(FieldSet::FULL).build().unwrap()
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let parser = create_parser();
+        let browser = parser.parse(PROXY);
+        let device = parser.parse(IPHONE);
+
+        let hinted = parser.parse_headers([
+            ("user-agent", PROXY),
+            ("Device-Stock-UA", "ignored, lower priority"),
+            ("X-OperaMini-Phone-UA", IPHONE),
+            ("Sec-CH-UA-Mobile", "?1"),
+        ]);
+        assert_eq!(hinted.capabilities(), browser);
+        assert_eq!(hinted.fallback(), None);
+        assert_eq!(hinted.get_value(&BROWSER), browser.get_value(&BROWSER));
+        assert_eq!(hinted.provenance(&BROWSER), Some(Provenance::UserAgent));
+        for field in DEVICE_FIELDS {
+            let expected = match device.get_field(field) {
+                FieldValue::Value(value) => Some(value),
+                _ => browser.get_value(field),
+            };
+            if *field != IS_MOBILE_DEVICE {
+                assert_eq!(hinted.get_value(field), expected, "{}", field.name());
+            }
+        }
+        assert_ne!(device.get_value(&DEVICE_TYPE), browser.get_value(&DEVICE_TYPE));
+        assert_eq!(hinted.provenance(&DEVICE_TYPE), Some(Provenance::DeviceUserAgent));
+        assert_eq!(hinted.get_value(&IS_MOBILE_DEVICE), Some("true"));
+        assert_eq!(hinted.provenance(&IS_MOBILE_DEVICE), Some(Provenance::ClientHints));
+
+        let hinted = parser.parse_headers([("X-Device-User-Agent", IPHONE), ("User-Agent", "")]);
+        assert_eq!(hinted.capabilities(), device);
+        assert!(hinted.iter().all(|(_, _, source)| source == Provenance::DeviceUserAgent));
+        assert_eq!(hinted.provenance(&BROWSER), Some(Provenance::DeviceUserAgent));
+
+        let hinted = parser.parse_headers([("Accept", "*/*")]);
+        assert_eq!(hinted.fallback(), Some(Fallback::Empty));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_parse_header_map() {
+        let parser = create_parser();
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::USER_AGENT, PROXY.parse().unwrap());
+        headers.insert("x-operamini-phone-ua", IPHONE.parse().unwrap());
+        let hinted = parser.parse_header_map(&headers);
+        let expected = parser.parse_headers([
+            ("User-Agent", PROXY),
+            ("X-OperaMini-Phone-UA", IPHONE),
+        ]);
+        assert_eq!(hinted.capabilities(), expected.capabilities());
+        assert!(hinted.iter().eq(expected.iter()));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::ParseError;
+use crate::{BrowsCapField, PARENT, capabilities};
+use hashbrown::HashMap;
+
+//INI文件开头的版本信息，不是rule
+const VERSION_SECTION: &str = "GJK_Browscap_Version";
+//沿Parent向上查找的最大层数，超过时认为Parent成环
+const MAX_DEPTH: usize = 64;
+
+/*
+    browscap的INI文件（php_browscap.ini的格式）：每个[pattern]节只写出与Parent不同的键，
+    其余的值沿Parent链继承。键与CSV的列名相同，所以每节展开后和CSV的一条记录一样
+*/
+pub(crate) struct IniFile<'t> {
+    sections: Vec<Section<'t>>,
+    by_name: HashMap<&'t str, usize>,
+}
+
+struct Section<'t> {
+    name: &'t str,
+    line: u64,
+    byte: u64,
+    keys: Vec<(&'t str, &'t str)>,
+}
+
+//一节展开的结果，与CSV记录一样带着行号和起始字节
+pub(crate) struct IniRecord<'t> {
+    pub(crate) line: u64,
+    pub(crate) byte: u64,
+    pub(crate) prepared: Result<(String, Vec<&'t str>), ParseError>,
+}
+
+impl<'t> Section<'t> {
+    fn get(&self, key: &str) -> Option<&'t str> {
+        self.keys
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| *value)
+    }
+}
+
+impl<'t> IniFile<'t> {
+    //;开头的行是注释，第一个节之前的键被忽略
+    pub(crate) fn new(text: &'t str) -> IniFile<'t> {
+        let mut sections: Vec<Section<'t>> = Vec::new();
+        let mut byte = 0;
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let start = byte;
+            byte += line.len() as u64;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let line = i as u64 + 1;
+                sections.push(Section { name, line, byte: start, keys: Vec::new() });
+            } else if let (Some(section), Some((key, value))) =
+                (sections.last_mut(), line.split_once('='))
+            {
+                section.keys.push((key.trim(), unquote(value.trim())));
+            }
+        }
+        let by_name = sections
+            .iter()
+            .enumerate()
+            .map(|(i, section)| (section.name, i))
+            .collect();
+        IniFile { sections, by_name }
+    }
+
+    //按文件中的顺序展开每个rule节的pattern和fields的值
+    pub(crate) fn records<'a>(
+        &'a self,
+        fields: &'a [&'static BrowsCapField],
+    ) -> impl Iterator<Item = IniRecord<'t>> + 'a {
+        self.sections
+            .iter()
+            .filter(|section| section.name != VERSION_SECTION)
+            .map(move |section| IniRecord {
+                line: section.line,
+                byte: section.byte,
+                prepared: self.resolve(section, fields).map(|values| {
+                    (crate::rule::normalize_pattern(section.name), values)
+                }),
+            })
+    }
+
+    //每个字段取本节或最近的有这个键的祖先节中的值
+    fn resolve(
+        &self,
+        section: &Section<'t>,
+        fields: &[&'static BrowsCapField],
+    ) -> Result<Vec<&'t str>, ParseError> {
+        let mut chain = vec![section];
+        while let Some(parent) = chain[chain.len() - 1].get(PARENT.column()) {
+            let index = self.by_name.get(parent).filter(|_| chain.len() < MAX_DEPTH);
+            let index = index.ok_or_else(|| ParseError::Parent(parent.into()))?;
+            chain.push(&self.sections[*index]);
+        }
+        let values = fields.iter().map(|field| {
+            match chain.iter().find_map(|section| section.get(field.column())) {
+                Some(value) if !value.is_empty() => value,
+                _ => capabilities::MISSING_VALUE,
+            }
+        });
+        Ok(values.collect())
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod test_ini_file {
+    use super::*;
+    use crate::{BROWSER, BROWSER_VERSION, IS_CRAWLER, PLATFORM};
+
+    const INI: &str = r#";;; Provided courtesy of https://browscap.org/
+[GJK_Browscap_Version]
+Version=6001008
+
+[DefaultProperties]
+Browser="DefaultProperties"
+Version="0.0"
+Crawler="false"
+
+[Chrome 126.0]
+Parent="DefaultProperties"
+Browser="Chrome"
+Version="126.0"
+
+[Mozilla/5.0 (*Windows NT 10.0*) applewebkit* (*khtml*like*gecko*) Chrome/126.0*Safari/*]
+Parent="Chrome 126.0"
+Platform="Win10"
+
+[Orphan*]
+Parent="Missing"
+"#;
+
+    #[test]
+    fn test_inherit_from_parent() {
+        let ini = IniFile::new(INI);
+        let fields = [&BROWSER, &BROWSER_VERSION, &PLATFORM, &IS_CRAWLER];
+        let records: Vec<IniRecord> = ini.records(&fields).collect();
+        assert_eq!(records.len(), 4);
+        let (pattern, values) = records[2].prepared.as_ref().unwrap();
+        assert!(pattern.starts_with("mozilla/5.0 (*windows nt 10.0*)"));
+        assert_eq!(*values, ["Chrome", "126.0", "Win10", "false"]);
+        assert_eq!(records[2].line, 15);
+
+        let (_, values) = records[0].prepared.as_ref().unwrap();
+        assert_eq!(values[2], capabilities::MISSING_VALUE);
+        let orphan = &records[3].prepared;
+        assert!(matches!(orphan, Err(ParseError::Parent(parent)) if parent == "Missing"));
+    }
+
+    #[test]
+    fn test_parent_cycle() {
+        let ini = IniFile::new("[a*]\nParent=\"b*\"\n[b*]\nParent=\"a*\"\n");
+        let records: Vec<IniRecord> = ini.records(&[&BROWSER]).collect();
+        assert!(records.iter().all(|record| record.prepared.is_err()));
+    }
+}
@@ -1,22 +1,44 @@
-use crate::error::ParseError;
-use crate::file_parser::FileParser;
-use crate::filter::Filter;
+use crate::access_log::LogToken;
+use crate::capabilities::{CapabilitySchema, CapabilityTable, CustomValues};
+use crate::overlay::CustomRule;
+use crate::literal::LiteralArena;
 use crate::rule::Rule;
-use std::fs::File;
-use std::io::BufReader;
-use hashbrown::HashSet;
-use ustr::Ustr;
+use crate::rule_index::IndexScratch;
+use crate::user_agent_parser::Matcher;
+use crate::searchable_string::SearchableString;
+use bitvec::vec::BitVec;
+use hashbrown::{HashMap, HashSet};
+use roaring::RoaringBitmap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use crate::error::ParseError;
 
 mod brows_cap_field;
 mod capabilities;
 mod error;
-mod mapper;
 mod rule;
 mod searchable_string;
 mod file_parser;
 pub mod user_agent_parser;
 mod filter;
 mod literal;
+mod parser_builder;
+mod rule_index;
+mod pattern_set;
+mod memory_stats;
+mod load_report;
+mod data_path;
+mod client_hints;
+mod headers;
+mod access_log;
+mod aggregator;
+mod dataset_diff;
+mod overlay;
+mod field_set;
+mod ini_file;
 
 // 枚举常量定义
 pub const IS_MASTER_PARENT: BrowsCapField = BrowsCapField::new("IS_MASTER_PARENT", false, 0);
@@ -70,7 +92,7 @@ pub const RENDERING_ENGINE_VERSION: BrowsCapField = BrowsCapField::new("RENDERIN
 pub const RENDERING_ENGINE_DESCRIPTION: BrowsCapField = BrowsCapField::new("RENDERING_ENGINE_DESCRIPTION", false, 48);
 pub const RENDERING_ENGINE_MAKER: BrowsCapField = BrowsCapField::new("RENDERING_ENGINE_MAKER", false, 49);
 
-const DEFAULT_FILE_NAME: &'static str = "browscap_sorted.csv";
+const DEFAULT_FILE_NAME: &str = "browscap_sorted.csv";
 
 pub trait Predicate<T> {
     fn test(&self, value: &T) -> bool;
@@ -93,57 +115,371 @@ pub struct BrowsCapField {
     ordinal: usize,
 }
 
-#[derive(Debug)]
+/// A set of [`BrowsCapField`]s stored as one bit per field, with presets for the fields of the
+/// browscap lite, standard and full files. Combine sets with `|`, `&` and `-`, or with fields:
+/// `FieldSet::LITE | &IS_CRAWLER`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FieldSet(u64);
+
+#[derive(Clone)]
 pub struct Capabilities {
-    my_values: Vec<Ustr>,
+    my_schema: Arc<CapabilitySchema>,
+    my_row: Box<[u64]>,
 }
 
-#[derive(Debug)]
+/// Reusable working memory for [`UserAgentParser::parse_with`].
+///
+/// Keeping one `Scratch` per thread lets steady-state parsing with [`MatchEngine::Rules`] run
+/// without allocating.
+#[derive(Debug, Default)]
+pub struct Scratch {
+    my_search: SearchableString,
+    my_found_tokens: BitVec,
+    my_includes: BitVec,
+    my_sparse_excludes: RoaringBitmap,
+    my_index: IndexScratch,
+}
+
+#[derive(Debug, Clone)]
 pub struct UserAgentParser {
     my_rules: Vec<Rule>,
-    my_filters: Vec<Filter>,
+    my_literals: LiteralArena,
+    my_capabilities: CapabilityTable,
+    my_matcher: Matcher,
+    //按RulePriority排列的三段rule
+    my_tiers: [Range<usize>; 3],
+    my_max_length: Option<usize>,
+}
+
+/// Why a [`ParseOutcome`] holds fallback capabilities instead of those of a matching rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fallback {
+    /// The user agent was empty.
+    Empty,
+    /// No rule matched the user agent.
+    Unmatched,
+    /// The user agent was longer than [`ParserBuilder::max_length`] and was not matched.
+    TooLong,
+}
+
+impl Fallback {
+    const ALL: [Fallback; 3] = [Fallback::Empty, Fallback::Unmatched, Fallback::TooLong];
+}
+
+/// The result of [`UserAgentParser::parse_outcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOutcome<'a> {
+    pub capabilities: &'a Capabilities,
+    /// Set when `capabilities` are the fallback for an empty, unmatched or too long user agent.
+    pub fallback: Option<Fallback>,
+}
+
+/// The value of one field of a [`Capabilities`], see [`Capabilities::get_field`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldValue<'a> {
+    /// The value from the browscap data, which may itself be the literal `"Unknown"`.
+    Value(&'a str),
+    /// The browscap data has no value for the field, or no rule matched.
+    Unknown,
+    /// The field was not loaded, see [`ParserBuilder::fields`].
+    NotLoaded,
+}
+
+impl<'a> FieldValue<'a> {
+    pub fn value(self) -> Option<&'a str> {
+        match self {
+            FieldValue::Value(value) => Some(value),
+            FieldValue::Unknown | FieldValue::NotLoaded => None,
+        }
+    }
+}
+
+/// Raw values of the User-Agent Client Hints request headers, each optional. See
+/// [`ClientHints::apply`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientHints<'h> {
+    /// `Sec-CH-UA`
+    pub ua: Option<&'h str>,
+    /// `Sec-CH-UA-Full-Version-List`
+    pub full_version_list: Option<&'h str>,
+    /// `Sec-CH-UA-Platform`
+    pub platform: Option<&'h str>,
+    /// `Sec-CH-UA-Platform-Version`
+    pub platform_version: Option<&'h str>,
+    /// `Sec-CH-UA-Mobile`
+    pub mobile: Option<&'h str>,
+    /// `Sec-CH-UA-Model`
+    pub model: Option<&'h str>,
+}
+
+/// [`Capabilities`] with values from [`ClientHints`] or from alternate user agent headers laid
+/// over them.
+#[derive(Debug, Clone)]
+pub struct HintedCapabilities<'a> {
+    my_capabilities: &'a Capabilities,
+    my_fallback: Option<Fallback>,
+    //my_capabilities是从哪个header匹配的
+    my_source: Provenance,
+    my_overrides: Vec<(&'static BrowsCapField, String, Provenance)>,
+}
+
+/// Where a value of [`HintedCapabilities`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Provenance {
+    /// Matched by browscap from the user agent.
+    UserAgent,
+    /// Matched by browscap from a header carrying the device's own user agent, see
+    /// [`UserAgentParser::parse_headers`].
+    DeviceUserAgent,
+    /// Taken from the client hints.
+    ClientHints,
+}
+
+/// The layout of access log lines, used to find the user agent in each of them. See
+/// [`LogFormat::parse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormat {
+    my_tokens: Vec<LogToken>,
+}
+
+/// Line counts of [`UserAgentParser::enrich_log`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogSummary {
+    /// Lines read.
+    pub lines: u64,
+    /// Lines that did not match the [`LogFormat`] and were written without capabilities.
+    pub unmatched: u64,
+}
+
+/// Counts [`Capabilities`] by the values of chosen fields or combinations of fields, for traffic
+/// reports. See [`Aggregator::count`].
+#[derive(Debug, Clone, Default)]
+pub struct Aggregator {
+    my_keys: Vec<Vec<&'static BrowsCapField>>,
+    my_counts: Vec<HashMap<String, u64>>,
+    my_exclude_crawlers: bool,
+    my_total: u64,
+    my_crawlers: u64,
+}
+
+/// A combination of values and how often it was counted, see [`Aggregator::top`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateEntry<'a> {
+    /// The values, one per field of the key.
+    pub values: Vec<&'a str>,
+    pub count: u64,
+    /// The share of all counted capabilities, from 0 to 100.
+    pub percentage: f64,
+}
+
+/// How the values of fields change for a corpus of user agents from one parser to another,
+/// typically loaded from two browscap releases. See [`DatasetDiff::add`].
+#[derive(Debug, Clone)]
+pub struct DatasetDiff {
+    my_fields: Vec<&'static BrowsCapField>,
+    my_max_examples: usize,
+    my_total: u64,
+    my_changed: u64,
+    //(字段序号, 旧值, 新值) -> (次数, 示例)
+    my_changes: HashMap<(usize, String, String), (u64, Vec<String>)>,
 }
 
+/// A change of one field from one value to another, see [`DatasetDiff::changes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange<'a> {
+    pub field: &'static BrowsCapField,
+    pub old: &'a str,
+    pub new: &'a str,
+    /// User agents whose value changed like this.
+    pub count: u64,
+    /// The first of these user agents, see [`DatasetDiff::max_examples`].
+    pub examples: &'a [String],
+}
+
+/// How a [`UserAgentParser`] finds the rule matching a user agent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchEngine {
+    /// Rules that pass the token filters are matched one by one in priority order.
+    #[default]
+    Rules,
+    /// Every pattern is compiled into one [`regex::bytes::RegexSet`] that reports all matching
+    /// rules in a single pass over the user agent. It needs no filters, but the automaton has to
+    /// track every wildcard pattern at once, so on a full browscap file each parse is several
+    /// orders of magnitude slower than [`MatchEngine::Rules`].
+    RegexSet,
+}
+
+/// Approximate heap usage of a loaded parser, see [`UserAgentParser::memory_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// The rules and their lists of literals.
+    pub rule_bytes: usize,
+    /// The literals shared by the rules.
+    pub literal_bytes: usize,
+    /// Filter masks and the token automaton.
+    pub filter_bytes: usize,
+    /// The prefix/postfix rule index, when enabled.
+    pub index_bytes: usize,
+    /// Capability rows, field layout and value dictionaries.
+    pub capability_bytes: usize,
+    /// Contents of literal and filter strings and of dictionary values.
+    pub string_bytes: usize,
+    /// The compiled [`MatchEngine::RegexSet`]: `Some(0)` for [`MatchEngine::Rules`], and `None`
+    /// for `RegexSet` because the regex crate does not report the size of its automaton, which
+    /// is usually the largest part of such a parser. [`MemoryStats::total_bytes`] leaves it out.
+    pub pattern_set_bytes: Option<usize>,
+    pub rule_count: usize,
+    pub capability_count: usize,
+    pub literal_count: usize,
+    pub filter_count: usize,
+}
+
+/// What happened while loading a [`UserAgentParser`], see [`ParserBuilder::build_with_report`].
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// The CSV file that was loaded.
+    pub file: PathBuf,
+    /// Reading the CSV file and creating the rules.
+    pub read_time: Duration,
+    /// Ordering the rules by priority.
+    pub sort_time: Duration,
+    /// Building the filter masks, zero for [`MatchEngine::RegexSet`].
+    pub filter_time: Duration,
+    /// Building the rule index or compiling the [`MatchEngine::RegexSet`].
+    pub index_time: Duration,
+    pub total_time: Duration,
+    /// CSV records after the header, including skipped ones.
+    pub records_read: usize,
+    pub skipped: Vec<SkippedRecord>,
+    /// Rules created from the records.
+    pub rules_created: usize,
+    /// Records left out by [`RuleOverlay::suppress`]. With the rules created, the filtered and
+    /// the skipped records they add up to the records read.
+    pub suppressed: usize,
+    /// Records left out by [`ParserBuilder::rule_filter`].
+    pub filtered: usize,
+    /// Rules added from the [`RuleOverlay`].
+    pub custom_rules: usize,
+    /// Rules with the same pattern as another rule. Only the first of them can ever match.
+    pub duplicates: usize,
+    pub filter_count: usize,
+}
+
+/// A CSV record that did not become a rule.
+#[derive(Debug)]
+pub struct SkippedRecord {
+    /// Line of the record in the file, starting at 1.
+    pub line: u64,
+    pub reason: ParseError,
+}
+
+/// The phases of loading a parser, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadPhase {
+    Reading,
+    Sorting,
+    Filtering,
+    Indexing,
+}
+
+/// Passed to the callback set with [`ParserBuilder::progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub phase: LoadPhase,
+    pub records_read: usize,
+    pub bytes_read: u64,
+    /// Size of the CSV file, if known.
+    pub total_bytes: Option<u64>,
+}
+
+type ProgressCallback = dyn FnMut(&LoadProgress) -> bool + Send;
+
+type RulePredicate = dyn Predicate<Capabilities> + Send;
+
+/// Configures and loads a [`UserAgentParser`], see [`UserAgentParser::builder`].
+pub struct ParserBuilder {
+    my_fields: FieldSet,
+    my_file: Option<PathBuf>,
+    my_fallbacks: Vec<PathBuf>,
+    my_options: ParserOptions,
+    my_progress: Option<Box<ProgressCallback>>,
+    my_overlay: RuleOverlay,
+    my_rule_filters: Vec<Box<RulePredicate>>,
+}
+
+/// Changes to the browscap rules made while loading: custom rules with their own capabilities,
+/// browscap rules with some values replaced, and browscap rules left out. See
+/// [`ParserBuilder::overlay`].
+#[derive(Debug, Clone, Default)]
+pub struct RuleOverlay {
+    my_rules: Vec<CustomRule>,
+    //规范化的pattern -> 替换的值，None表示去掉该rule
+    my_overrides: HashMap<String, Option<CustomValues>>,
+}
+
+/// Where a custom rule of a [`RuleOverlay`] goes among the browscap rules, which are tried
+/// longest pattern first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RulePriority {
+    /// Before every browscap rule.
+    Before,
+    /// Among the browscap rules by the length of its pattern, like a browscap rule.
+    #[default]
+    Ordered,
+    /// After every browscap rule, so it only matches user agents no browscap rule matches.
+    After,
+}
 
+//构建UserAgentParser时的选项
+#[derive(Debug, Clone)]
+pub(crate) struct ParserOptions {
+    engine: MatchEngine,
+    rule_index: bool,
+    compressed_masks: bool,
+    unknown_value: Box<str>,
+    max_length: Option<usize>,
+    //按Fallback索引，None时使用内置的capabilities
+    fallbacks: [Option<CustomValues>; 3],
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        ParserOptions {
+            engine: MatchEngine::default(),
+            rule_index: false,
+            compressed_masks: false,
+            unknown_value: capabilities::UNKNOWN_BROWSCAP_VALUE.into(),
+            max_length: None,
+            fallbacks: Default::default(),
+        }
+    }
+}
+
+
+/// Loads the default fields from the browscap file found as described at [`ParserBuilder::file`].
 pub fn load_parser_default() -> Result<UserAgentParser, ParseError> {
-    load_parser_with_fields(default_fields())
+    load_parser_with_fields(FieldSet::DEFAULT)
 }
 
+/// Loads `fields`, a [`FieldSet`] or a list of fields, in addition to the default ones.
 pub fn load_parser_with_fields(
-    fields: Vec<&'static BrowsCapField>,
+    fields: impl Into<FieldSet>,
 ) -> Result<UserAgentParser, ParseError> {
-    create_parser_by_file(fields, DEFAULT_FILE_NAME)
+    UserAgentParser::builder().fields(fields).build()
 }
 
 pub fn create_parser_by_file(
-    fields: Vec<&'static BrowsCapField>,
+    fields: impl Into<FieldSet>,
     file_name: & str
 ) -> Result<UserAgentParser, ParseError> {
-    let file = File::open(file_name).unwrap();
-    let merged_unique_fields = merge_fields(fields);
-    let reader = BufReader::new(file);
-    let mut file_parser=FileParser::new(merged_unique_fields);
-    file_parser.parse(reader);
-    let user_agent_parser=file_parser::create_agent_parser(file_parser);
-    Ok(user_agent_parser)
-}
-
- fn default_fields() -> Vec<&'static BrowsCapField> {
-    BrowsCapField::values()
-        .iter()
-        .filter(|i| i.is_default())
-        .collect()
+    UserAgentParser::builder()
+        .fields(fields)
+        .file(file_name)
+        .build()
 }
 
-fn merge_fields(fields: Vec<&'static BrowsCapField>) -> HashSet<&'static BrowsCapField> {
-    let mut unique_fields: HashSet<&BrowsCapField> = HashSet::new();
-    fields.into_iter().for_each(|i| {
-        unique_fields.insert(i);
-    });
-    default_fields().into_iter().for_each(|i| {
-        unique_fields.insert(i);
-    });
-    unique_fields
+fn merge_fields(fields: FieldSet) -> HashSet<&'static BrowsCapField> {
+    (fields | FieldSet::DEFAULT).iter().collect()
 }
 
 
@@ -152,7 +488,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_load_fields() {
-        let fields = default_fields();
+        let fields = FieldSet::DEFAULT;
         for field in fields.iter() {
             println!("{}", field.name());
 
@@ -163,10 +499,7 @@ mod tests {
 
     #[test]
     fn test_merge_fields(){
-        let mut my_fields:Vec<&'static BrowsCapField>=Vec::new();
-        my_fields.push(&BROWSER);
-        my_fields.push(&IS_BETA);
-        my_fields.push(&PLATFORM);
+        let my_fields = FieldSet::of(&[&BROWSER, &IS_BETA, &PLATFORM]);
         let merge_fields = merge_fields(my_fields);
         for merge_field in merge_fields.iter() {
             println!("{}", merge_field.name())
@@ -174,4 +507,12 @@ mod tests {
         println!("-------------");
         assert_eq!(merge_fields.len(), 7)
     }
+
+    #[test]
+    fn test_reload_literal_count() {
+        let parser = load_parser_default().unwrap();
+        let reloaded = load_parser_default().unwrap();
+        assert!(parser.get_literal_count() > 0);
+        assert_eq!(parser.get_literal_count(), reloaded.get_literal_count());
+    }
 }
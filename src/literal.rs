@@ -1,21 +1,17 @@
-use crate::searchable_string::SearchableString;
 use std::fmt::{Debug};
-use std::sync::atomic::{AtomicIsize, Ordering};
-use std::sync::{Arc, LazyLock};
-use dashmap::DashMap;
+use std::sync::Arc;
+use hashbrown::HashMap;
+use memchr::memmem::Finder;
 use ustr::Ustr;
 
-pub static MY_NR_OF_INSTANCES: AtomicIsize = AtomicIsize::new(0);
-
-pub static LITERAL_CACHE: LazyLock<DashMap<Ustr, Arc<Literal>>> = LazyLock::new(|| DashMap::new());
-
+#[derive(Clone)]
 pub struct Literal {
     pub(crate) my_string: Ustr,
     pub(crate) my_index: usize,
-}
-pub fn get_searchable_string(contents: String) -> SearchableString {
-    let max_index = MY_NR_OF_INSTANCES.load(Ordering::SeqCst) + 1;
-    SearchableString::new(contents, max_index as usize)
+    //最长的不含?的片段及其在my_string中的偏移，用于SIMD子串搜索
+    my_anchor: Finder<'static>,
+    my_anchor_offset: usize,
+    my_has_wildcard: bool,
 }
 
 impl Debug for Literal {
@@ -28,38 +24,136 @@ impl Debug for Literal {
     }
 }
 
+/*
+    每个parser自己的literal池：相同内容的literal只创建一次，下标从0开始连续分配。
+    SearchableString的缓存按下标寻址，下标连续缓存就紧凑，重新加载parser也不会让下标一直增长。
+    parser构建完成时登记的literal冻结到my_shared，复制parser时共享，之后插入rule新增的literal在my_literals
+*/
+#[derive(Debug, Clone, Default)]
+pub struct LiteralArena {
+    my_shared: Arc<HashMap<Ustr, Arc<Literal>>>,
+    my_literals: HashMap<Ustr, Arc<Literal>>,
+    my_nr_of_instances: usize,
+}
+
+impl LiteralArena {
+    pub fn new() -> LiteralArena {
+        LiteralArena::default()
+    }
+
+    pub fn get_literal(&mut self, value: &str) -> Arc<Literal> {
+        let value = Ustr::from(value);
+        let found = self.my_shared.get(&value).or_else(|| self.my_literals.get(&value));
+        if let Some(literal) = found {
+            return literal.clone();
+        }
+        let literal = Arc::new(self.create_literal(value));
+        self.my_literals.insert(value, literal.clone());
+        literal
+    }
+
+    //创建一个不参与去重的literal，下标同样由本池分配
+    pub fn create_literal(&mut self, contents: Ustr) -> Literal {
+        let literal = Literal::create_literal(contents, self.my_nr_of_instances);
+        self.my_nr_of_instances += 1;
+        literal
+    }
+
+    //把已登记的literal移到共享的表中，此后复制arena不再复制它们
+    pub(crate) fn freeze(&mut self) {
+        if !self.my_literals.is_empty() {
+            Arc::make_mut(&mut self.my_shared).extend(self.my_literals.drain());
+        }
+    }
+
+    //已分配的下标个数
+    pub fn len(&self) -> usize {
+        self.my_nr_of_instances
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.my_nr_of_instances == 0
+    }
+
+    //literal本身和去重用的HashMap占用的堆内存，不含字符串内容
+    pub(crate) fn memory_usage(&self) -> usize {
+        let entry = size_of::<(Ustr, Arc<Literal>)>() + 1;
+        let literal = size_of::<Literal>() + 2 * size_of::<usize>();
+        (self.my_shared.capacity() + self.my_literals.capacity()) * entry
+            + (self.my_shared.len() + self.my_literals.len()) * literal
+    }
+
+    //literal字符串在Ustr全局表中占用的字节数
+    pub(crate) fn string_bytes(&self) -> usize {
+        self.my_shared
+            .keys()
+            .chain(self.my_literals.keys())
+            .map(|value| value.len())
+            .sum()
+    }
+}
+
 impl Literal {
-    pub fn create_literal(contents: Ustr) -> Literal {
+    pub(crate) fn create_literal(contents: Ustr, index: usize) -> Literal {
+        let bytes: &'static [u8] = contents.as_str().as_bytes();
+        let (my_anchor_offset, anchor) = longest_fixed_segment(bytes);
         Literal {
             my_string: contents,
-            my_index: MY_NR_OF_INSTANCES.fetch_add(1, Ordering::SeqCst) as usize,
+            my_index: index,
+            my_anchor: Finder::new(anchor),
+            my_anchor_offset,
+            my_has_wildcard: bytes.contains(&b'?'),
         }
     }
 
-    pub(crate) fn get_first_char(&self) -> char {
-        self.my_string.chars().next().unwrap()
-    }
-
     pub(crate) fn get_length(&self) -> usize {
         self.my_string.len()
     }
 
     //Checks whether the value represents a complete substring from the from index.
     //glob匹配
-    //逐个字符比较，忽略?不相等（即匹配全部单个字符）
-    pub(crate) fn matches(&self, value: &Vec<char>, from: i32) -> bool {
+    //逐个字节比较，忽略?不相等（即匹配任意单个字节）。
+    //长度按字节固定，所以一个非ASCII字符要用与其UTF-8字节数相同个数的?匹配
+    pub(crate) fn matches(&self, value: &[u8], from: i32) -> bool {
         let len = self.my_string.len() as i32;
         if len + from > value.len() as i32 || from < 0 {
             return false;
         }
+        let candidate = &value[from as usize..(from + len) as usize];
+        if !self.my_has_wildcard {
+            return candidate == self.my_string.as_bytes();
+        }
+        self.my_string
+            .bytes()
+            .zip(candidate)
+            .all(|(ci, vi)| ci == *vi || ci == b'?')
+    }
 
-        for (i, ci) in self.my_string.chars().enumerate() {
-            let vi = value[i + from as usize];
-            if ci != vi && ci != '?' {
-                return false;
+    /// Appends every (possibly overlapping) start position of this literal in `value` to
+    /// `positions`, in increasing order.
+    pub(crate) fn find_all(&self, value: &[u8], positions: &mut Vec<u32>) {
+        let len = self.my_string.len();
+        if len > value.len() {
+            return;
+        }
+        if self.my_anchor.needle().is_empty() {
+            // Only '?' wildcards, every position with enough room matches
+            positions.extend(0..=(value.len() - len) as u32);
+            return;
+        }
+        // Search the fixed anchor with memmem and verify the wildcards around it
+        let last_start = value.len() - len;
+        let mut from = self.my_anchor_offset;
+        while let Some(found) = self.my_anchor.find(&value[from..]) {
+            let start = from + found - self.my_anchor_offset;
+            if start > last_start {
+                break;
+            }
+            if !self.my_has_wildcard || self.matches(value, start as i32) {
+                positions.push(start as u32);
             }
+            from += found + 1;
         }
-        true
     }
 
     pub(crate) fn get_index(&self) -> usize {
@@ -81,12 +175,17 @@ impl Literal {
 }
 
 
-pub fn get_literal(value: &str) -> Arc<Literal> {
-    let value = Ustr::from(value);
-    LITERAL_CACHE
-        .entry(value)
-        .or_insert_with(|| Arc::new(Literal::create_literal(value)))
-        .clone()
+//返回最长的不含?片段的(偏移, 片段)
+fn longest_fixed_segment(bytes: &'static [u8]) -> (usize, &'static [u8]) {
+    let mut best: (usize, &'static [u8]) = (0, &[]);
+    let mut offset = 0;
+    for segment in bytes.split(|b| *b == b'?') {
+        if segment.len() > best.1.len() {
+            best = (offset, segment);
+        }
+        offset += segment.len() + 1;
+    }
+    best
 }
 
 #[cfg(test)]
@@ -96,37 +195,109 @@ mod test_literal {
     #[test]
     fn test_literal_basic() {
         let str = "abcdef";
-        let literal = Literal::create_literal(Ustr::from(str));
+        let mut arena = LiteralArena::new();
+        let literal = arena.create_literal(Ustr::from(str));
         assert_eq!(str.len(), literal.get_length());
-        assert_eq!('a', literal.get_first_char());
+        assert_eq!(literal.my_anchor.needle(), b"abcdef");
         assert_eq!(literal.get_string(), literal.get_string());
-        let literal2 = Literal::create_literal(Ustr::from("di"));
-        assert_eq!(literal2.get_index(), 1)
+        assert_eq!(0, literal.get_index());
+        let literal2 = arena.create_literal(Ustr::from("di"));
+        assert_eq!(1, literal2.get_index());
+    }
+
+    #[test]
+    fn test_literal_arena() {
+        let mut arena = LiteralArena::new();
+        let abc = arena.get_literal("abc");
+        let def = arena.get_literal("def");
+        assert!(Arc::ptr_eq(&abc, &arena.get_literal("abc")));
+        assert_eq!((0, 1), (abc.get_index(), def.get_index()));
+        assert_eq!(2, arena.len());
+
+        // 每个池的下标都从0开始
+        let mut other = LiteralArena::new();
+        assert_eq!(0, other.get_literal("def").get_index());
+
+        // 冻结后复制的池共享已登记的literal，新的literal只在复制的池中
+        arena.freeze();
+        let mut copy = arena.clone();
+        assert!(Arc::ptr_eq(&arena.my_shared, &copy.my_shared));
+        assert!(Arc::ptr_eq(&abc, &copy.get_literal("abc")));
+        assert_eq!(2, copy.get_literal("ghi").get_index());
+        assert_eq!((2, 3), (arena.len(), copy.len()));
+        assert_eq!(2, arena.get_literal("jkl").get_index());
     }
 
     #[test]
     fn test_literal_matches() {
-        let literal = Literal::create_literal(Ustr::from("def"));
-        let search: Vec<char> = "abcdef".chars().collect();
-        assert_eq!(literal.matches(&search, 3), true);
-        assert_eq!(literal.matches(&search, 0), false);
-        assert_eq!(literal.matches(&search, 5), false);
-        //assert_eq!(literal.matches(&search, -10), true);
-        assert_eq!(literal.matches(&search, 100), false);
+        let literal = Literal::create_literal(Ustr::from("def"), 0);
+        let search = "abcdef".as_bytes();
+        assert!(literal.matches(search, 3));
+        assert!(!literal.matches(search, 0));
+        assert!(!literal.matches(search, 5));
+        //assert_eq!(literal.matches(search, -10), true);
+        assert!(!literal.matches(search, 100));
 
-        let joker = Literal::create_literal("d?f".parse().unwrap());
-        assert_eq!(joker.matches(&search, 3), true);
-        assert_eq!(joker.matches(&search, 0), false);
-        assert_eq!(joker.matches(&search, 5), false);
+        let joker = Literal::create_literal("d?f".parse().unwrap(), 0);
+        assert!(joker.matches(search, 3));
+        assert!(!joker.matches(search, 0));
+        assert!(!joker.matches(search, 5));
     }
 
     #[test]
     fn test_literal_requires() {
-        let literal = Literal::create_literal(Ustr::from("hello"));
-        assert_eq!(literal.requires(Ustr::from("hello")), true);
-        assert_eq!(literal.requires(Ustr::from("hell")), true);
-        assert_eq!(literal.requires(Ustr::from("hello world")), false);
-        assert_eq!(literal.requires(Ustr::from("morning world")), false);
-        assert_eq!(literal.requires(Ustr::from("helloworld")), false);
+        let literal = Literal::create_literal(Ustr::from("hello"), 0);
+        assert!(literal.requires(Ustr::from("hello")));
+        assert!(literal.requires(Ustr::from("hell")));
+        assert!(!literal.requires(Ustr::from("hello world")));
+        assert!(!literal.requires(Ustr::from("morning world")));
+        assert!(!literal.requires(Ustr::from("helloworld")));
+    }
+
+    #[test]
+    fn test_literal_find_all() {
+        let mut positions = Vec::new();
+        let overlapping = Literal::create_literal(Ustr::from("aba"), 0);
+        overlapping.find_all(b"ababa", &mut positions);
+        assert_eq!(positions, vec![0, 2]);
+
+        positions.clear();
+        let joker = Literal::create_literal(Ustr::from("?b?"), 0);
+        joker.find_all(b"abcabc", &mut positions);
+        assert_eq!(positions, vec![0, 3]);
+
+        positions.clear();
+        let only_jokers = Literal::create_literal(Ustr::from("??"), 0);
+        only_jokers.find_all(b"abc", &mut positions);
+        assert_eq!(positions, vec![0, 1]);
+
+        positions.clear();
+        let too_long = Literal::create_literal(Ustr::from("abcd"), 0);
+        too_long.find_all(b"abc", &mut positions);
+        assert!(positions.is_empty());
+    }
+
+    #[test]
+    fn test_literal_non_ascii() {
+        // Positions and lengths are both counted in bytes
+        let value = "größe/1.0 safari".as_bytes();
+        let mut positions = Vec::new();
+        let safari = Literal::create_literal(Ustr::from("safari"), 0);
+        safari.find_all(value, &mut positions);
+        assert_eq!(positions, vec![12]);
+        assert!(safari.matches(value, (value.len() - safari.get_length()) as i32));
+
+        positions.clear();
+        let version = Literal::create_literal(Ustr::from("e/?.0"), 0);
+        version.find_all(value, &mut positions);
+        assert_eq!(positions, vec![6]);
+
+        // ?只匹配一个字节，两个字节的ö要用两个?
+        let one = Literal::create_literal(Ustr::from("gr?sse"), 0);
+        let two = Literal::create_literal(Ustr::from("gr??ße/"), 0);
+        assert!(one.matches(b"grosse", 0));
+        assert!(!one.matches("grösse".as_bytes(), 0));
+        assert!(two.matches(value, 0));
+        assert!(!Literal::create_literal(Ustr::from("gr?ße/"), 0).matches(value, 0));
     }
 }
@@ -0,0 +1,59 @@
+use crate::error::ParseError;
+use crate::{LoadPhase, LoadProgress, LoadReport, ProgressCallback, SkippedRecord};
+
+//读取阶段每隔多少条记录调用一次进度回调
+const PROGRESS_INTERVAL: usize = 1024;
+
+//加载过程中收集LoadReport，并在阶段开始和读取过程中调用进度回调
+pub(crate) struct LoadContext<'a> {
+    pub(crate) report: LoadReport,
+    progress: Option<&'a mut ProgressCallback>,
+    bytes_read: u64,
+    total_bytes: Option<u64>,
+}
+
+impl<'a> LoadContext<'a> {
+    pub(crate) fn new(
+        progress: Option<&'a mut ProgressCallback>,
+        total_bytes: Option<u64>,
+    ) -> LoadContext<'a> {
+        LoadContext {
+            report: LoadReport::default(),
+            progress,
+            bytes_read: 0,
+            total_bytes,
+        }
+    }
+
+    //回调返回false时取消加载
+    pub(crate) fn enter(&mut self, phase: LoadPhase) -> Result<(), ParseError> {
+        let Some(progress) = self.progress.as_mut() else {
+            return Ok(());
+        };
+        let current = LoadProgress {
+            phase,
+            records_read: self.report.records_read,
+            bytes_read: self.bytes_read,
+            total_bytes: self.total_bytes,
+        };
+        if progress(&current) {
+            Ok(())
+        } else {
+            Err(ParseError::Cancelled)
+        }
+    }
+
+    //每读完一条记录调用，byte是这条记录在文件中的起始位置
+    pub(crate) fn record_read(&mut self, byte: u64) -> Result<(), ParseError> {
+        self.report.records_read += 1;
+        self.bytes_read = byte;
+        if self.report.records_read.is_multiple_of(PROGRESS_INTERVAL) {
+            self.enter(LoadPhase::Reading)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn skip(&mut self, line: u64, reason: ParseError) {
+        self.report.skipped.push(SkippedRecord { line, reason });
+    }
+}
@@ -0,0 +1,88 @@
+use crate::rule::Rule;
+use crate::user_agent_parser::Matcher;
+use crate::{MemoryStats, UserAgentParser};
+
+impl UserAgentParser {
+    /// Estimates the heap memory used by this parser, from the capacities of its internal
+    /// buffers. Allocator overhead is not included, and the size of a compiled
+    /// [`crate::MatchEngine::RegexSet`] is unknown, see [`MemoryStats::pattern_set_bytes`].
+    pub fn memory_stats(&self) -> MemoryStats {
+        let rule_bytes = self.my_rules.capacity() * size_of::<Rule>()
+            + self.my_rules.iter().map(Rule::memory_usage).sum::<usize>();
+        let (filter_bytes, index_bytes, filter_count, filter_string_bytes, pattern_set_bytes) =
+            match &self.my_matcher {
+                Matcher::Rules(filters, index) => (
+                    filters.memory_usage(),
+                    index.as_ref().map_or(0, |index| index.memory_usage()),
+                    filters.len(),
+                    filters.string_bytes(),
+                    Some(0),
+                ),
+                // regex的RegexSet不提供自动机的大小
+                Matcher::PatternSet(_) => (0, 0, 0, 0, None),
+            };
+        MemoryStats {
+            rule_bytes,
+            literal_bytes: self.my_literals.memory_usage(),
+            filter_bytes,
+            index_bytes,
+            capability_bytes: self.my_capabilities.memory_usage(),
+            string_bytes: self.my_literals.string_bytes()
+                + filter_string_bytes
+                + self.my_capabilities.string_bytes(),
+            pattern_set_bytes,
+            rule_count: self.my_rules.len(),
+            capability_count: self.my_capabilities.len(),
+            literal_count: self.my_literals.len(),
+            filter_count,
+        }
+    }
+}
+
+impl MemoryStats {
+    /// The sum of the byte counts, without [`MemoryStats::pattern_set_bytes`] when it is
+    /// unknown.
+    pub fn total_bytes(&self) -> usize {
+        self.rule_bytes
+            + self.literal_bytes
+            + self.filter_bytes
+            + self.index_bytes
+            + self.capability_bytes
+            + self.string_bytes
+            + self.pattern_set_bytes.unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test_memory_stats {
+    use crate::{FieldSet, MatchEngine, UserAgentParser};
+
+    #[test]
+    fn test_memory_stats() {
+        let parser = UserAgentParser::builder().build().unwrap();
+        let stats = parser.memory_stats();
+        assert!(stats.rule_count > 0 && stats.capability_count > 0);
+        assert!(stats.literal_count > 0 && stats.filter_count > 0);
+        assert_eq!(stats.index_bytes, 0);
+        assert_eq!(stats.pattern_set_bytes, Some(0));
+        assert!(stats.total_bytes() > stats.rule_bytes + stats.filter_bytes);
+
+        let all_fields = UserAgentParser::builder()
+            .fields(FieldSet::FULL)
+            .rule_index(true)
+            .build()
+            .unwrap();
+        let all_stats = all_fields.memory_stats();
+        assert_eq!(all_stats.rule_count, stats.rule_count);
+        assert!(all_stats.capability_bytes > stats.capability_bytes);
+        assert!(all_stats.index_bytes > 0);
+
+        let regex_set = UserAgentParser::builder()
+            .engine(MatchEngine::RegexSet)
+            .build()
+            .unwrap();
+        let regex_stats = regex_set.memory_stats();
+        assert_eq!(regex_stats.pattern_set_bytes, None);
+        assert_eq!(regex_stats.rule_count, stats.rule_count);
+    }
+}
@@ -0,0 +1,566 @@
+use crate::capabilities::CustomValues;
+use crate::rule::normalize_pattern;
+use crate::{BrowsCapField, ParseError, RuleOverlay, RulePriority};
+use csv::ReaderBuilder;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+//overlay中的一条自定义rule，pattern保持原样，加载时再规范化
+#[derive(Debug, Clone)]
+pub(crate) struct CustomRule {
+    pub(crate) pattern: String,
+    pub(crate) priority: RulePriority,
+    pub(crate) values: CustomValues,
+}
+
+impl RuleOverlay {
+    pub fn new() -> RuleOverlay {
+        RuleOverlay::default()
+    }
+
+    /// Adds a rule for `pattern`, written like a browscap pattern with `*` and `?` wildcards,
+    /// that gives `values`. Fields missing from `values` have no value and fields that are not
+    /// loaded are ignored.
+    pub fn add(
+        mut self,
+        pattern: &str,
+        priority: RulePriority,
+        values: &[(&'static BrowsCapField, &str)],
+    ) -> RuleOverlay {
+        self.my_rules.push(CustomRule {
+            pattern: pattern.to_string(),
+            priority,
+            values: to_custom(values),
+        });
+        self
+    }
+
+    /// Replaces `values` in the capabilities of the browscap rules whose pattern is `pattern`,
+    /// ignoring ASCII case. Their other values and their place among the rules are kept.
+    pub fn replace_values(
+        mut self,
+        pattern: &str,
+        values: &[(&'static BrowsCapField, &str)],
+    ) -> RuleOverlay {
+        // 先前被去掉的pattern改为替换，多次替换时后给出的值优先
+        match self
+            .my_overrides
+            .entry(normalize_pattern(pattern))
+            .or_default()
+        {
+            Some(replaced) => replaced.extend(to_custom(values)),
+            suppressed => *suppressed = Some(to_custom(values)),
+        }
+        self
+    }
+
+    /// Leaves out the browscap rules whose pattern is `pattern`, ignoring ASCII case.
+    pub fn suppress(mut self, pattern: &str) -> RuleOverlay {
+        self.my_overrides.insert(normalize_pattern(pattern), None);
+        self
+    }
+
+    /// Adds the rules and changes of `other` after those of this overlay.
+    pub fn merge(mut self, other: RuleOverlay) -> RuleOverlay {
+        self.my_rules.extend(other.my_rules);
+        for (pattern, values) in other.my_overrides {
+            self = match values {
+                Some(values) => {
+                    let values: Vec<_> = values
+                        .iter()
+                        .map(|(field, value)| (*field, &**value))
+                        .collect();
+                    self.replace_values(&pattern, &values)
+                }
+                None => self.suppress(&pattern),
+            };
+        }
+        self
+    }
+
+    /// Reads an overlay from CSV with a header row. The `pattern` column is required, and the
+    /// `mode` column tells what to do with it:
+    ///
+    /// * `before`, `ordered` (also when the column is missing or empty) or `after` adds a rule
+    ///   with that [`RulePriority`], see [`RuleOverlay::add`]
+    /// * `replace` replaces values of the browscap rule, see [`RuleOverlay::replace_values`]
+    /// * `suppress` leaves the browscap rule out, see [`RuleOverlay::suppress`]
+    ///
+    /// Every other column is a field named like [`BrowsCapField::from_name`], such as
+    /// `browser` or `is_crawler`. Empty cells give no value.
+    ///
+    /// ```text
+    /// pattern,mode,browser,browser_type,is_crawler
+    /// ExampleApp/*,before,ExampleApp,Application,false
+    /// *partnerbot*,after,PartnerBot,Bot/Crawler,true
+    /// ```
+    pub fn from_csv(read: impl io::Read) -> Result<RuleOverlay, ParseError> {
+        let mut reader = ReaderBuilder::new().trim(csv::Trim::All).from_reader(read);
+        let headers = reader.headers().map_err(invalid_csv)?.clone();
+        let mut pattern_column = None;
+        let mut mode_column = None;
+        let mut fields = Vec::new();
+        for (i, header) in headers.iter().enumerate() {
+            match header.to_ascii_lowercase().as_str() {
+                "pattern" => pattern_column = Some(i),
+                "mode" => mode_column = Some(i),
+                name => match BrowsCapField::from_name(name) {
+                    Some(field) => fields.push((i, field)),
+                    None => return Err(ParseError::Overlay(format!("unknown column `{header}`"))),
+                },
+            }
+        }
+        let pattern_column =
+            pattern_column.ok_or_else(|| ParseError::Overlay("no `pattern` column".to_string()))?;
+
+        let mut overlay = RuleOverlay::new();
+        for record in reader.records() {
+            let record = record.map_err(invalid_csv)?;
+            let line = record.position().map_or(0, |position| position.line());
+            let pattern = record.get(pattern_column).unwrap_or_default();
+            let values: Vec<(&'static BrowsCapField, &str)> = fields
+                .iter()
+                .filter_map(|(i, field)| Some((*field, record.get(*i)?)))
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            let mode = mode_column.and_then(|i| record.get(i)).unwrap_or_default();
+            overlay = overlay.apply(line, pattern, mode, &values)?;
+        }
+        Ok(overlay)
+    }
+
+    /// Reads an overlay from TOML with the same schema as [`RuleOverlay::from_csv`]: each
+    /// `[[rule]]` table needs a `pattern`, may have a `mode`, and its other keys are fields.
+    /// Values are strings, booleans or numbers, and an empty string gives no value. Only this
+    /// part of TOML is read; other tables, arrays, inline tables and multi-line strings are
+    /// errors.
+    ///
+    /// ```text
+    /// [[rule]]
+    /// pattern = "ExampleApp/*"
+    /// mode = "before"
+    /// browser = "ExampleApp"
+    /// is_crawler = false
+    /// ```
+    pub fn from_toml(mut read: impl io::Read) -> Result<RuleOverlay, ParseError> {
+        let mut text = String::new();
+        read.read_to_string(&mut text)
+            .map_err(|error| ParseError::Overlay(error.to_string()))?;
+        let mut overlay = RuleOverlay::new();
+        for (line, table) in toml_rules(&text)? {
+            let mut pattern = None;
+            let mut mode = String::new();
+            let mut values = Vec::new();
+            for (key, value) in &table {
+                match key.to_ascii_lowercase().as_str() {
+                    "pattern" => pattern = Some(value.as_str()),
+                    "mode" => mode.clone_from(value),
+                    name => match BrowsCapField::from_name(name) {
+                        Some(field) if !value.is_empty() => values.push((field, value.as_str())),
+                        Some(_) => {}
+                        None => {
+                            let message = format!("line {line}: unknown key `{key}`");
+                            return Err(ParseError::Overlay(message));
+                        }
+                    },
+                }
+            }
+            let pattern = pattern.ok_or_else(|| {
+                ParseError::Overlay(format!("line {line}: rule without `pattern`"))
+            })?;
+            overlay = overlay.apply(line, pattern, &mode, &values)?;
+        }
+        Ok(overlay)
+    }
+
+    /// Reads an overlay from a file, as TOML if its extension is `.toml` (see
+    /// [`RuleOverlay::from_toml`]) and as CSV otherwise (see [`RuleOverlay::from_csv`]). A file
+    /// with another extension than `.csv` or `.toml` is an error.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<RuleOverlay, ParseError> {
+        let path = path.as_ref();
+        let is_toml = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+        if let Some(extension) = path.extension()
+            && !is_toml
+            && !extension.eq_ignore_ascii_case("csv")
+        {
+            return Err(ParseError::Overlay(format!(
+                "`{}`: only CSV and TOML overlay files are supported",
+                path.display()
+            )));
+        }
+        let file = File::open(path).map_err(|source| ParseError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if is_toml {
+            RuleOverlay::from_toml(file)
+        } else {
+            RuleOverlay::from_csv(io::BufReader::new(file))
+        }
+    }
+
+    //按mode处理一条记录，CSV和TOML共用
+    fn apply(
+        self,
+        line: u64,
+        pattern: &str,
+        mode: &str,
+        values: &[(&'static BrowsCapField, &str)],
+    ) -> Result<RuleOverlay, ParseError> {
+        Ok(match mode.to_ascii_lowercase().as_str() {
+            "before" => self.add(pattern, RulePriority::Before, values),
+            "" | "ordered" => self.add(pattern, RulePriority::Ordered, values),
+            "after" => self.add(pattern, RulePriority::After, values),
+            "replace" => self.replace_values(pattern, values),
+            "suppress" => self.suppress(pattern),
+            _ => {
+                return Err(ParseError::Overlay(format!(
+                    "line {line}: unknown mode `{mode}`"
+                )));
+            }
+        })
+    }
+
+    pub(crate) fn rules(&self) -> &[CustomRule] {
+        &self.my_rules
+    }
+
+    //规范化的pattern对应的改动：Some(None)为去掉，Some(Some(values))为替换这些值
+    pub(crate) fn override_for(&self, pattern: &str) -> Option<&Option<CustomValues>> {
+        self.my_overrides.get(pattern)
+    }
+}
+
+pub(crate) fn to_custom(values: &[(&'static BrowsCapField, &str)]) -> CustomValues {
+    values
+        .iter()
+        .map(|(field, value)| (*field, (*value).into()))
+        .collect()
+}
+
+fn invalid_csv(error: csv::Error) -> ParseError {
+    ParseError::Overlay(error.to_string())
+}
+
+//TOML表的起始行号和键值
+type TomlTable = (u64, Vec<(String, String)>);
+
+/*
+    读取overlay用到的TOML子集：[[rule]]表，裸键或带引号的键，值为字符串、布尔值或数字。
+    返回每个表的起始行号和按出现顺序排列的键值，布尔值和数字保持原文
+*/
+fn toml_rules(text: &str) -> Result<Vec<TomlTable>, ParseError> {
+    let mut tables: Vec<TomlTable> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i as u64 + 1;
+        let error = |message: &str| ParseError::Overlay(format!("line {line_number}: {message}"));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let header = line.split_once('#').map_or(line, |(header, _)| header).trim_end();
+            match header.strip_prefix("[[").and_then(|header| header.strip_suffix("]]")) {
+                Some(name) if name.trim() == "rule" => tables.push((line_number, Vec::new())),
+                _ => return Err(error("only [[rule]] tables are supported")),
+            }
+            continue;
+        }
+        let (key, rest) = toml_key(line).ok_or_else(|| error("expected `key = value`"))?;
+        let (value, rest) = toml_value(rest.trim_start()).map_err(|message| error(&message))?;
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error("unexpected text after the value"));
+        }
+        let (_, table) = tables
+            .last_mut()
+            .ok_or_else(|| error("key outside a [[rule]] table"))?;
+        if table.iter().any(|(existing, _)| *existing == key) {
+            return Err(error(&format!("duplicate key `{key}`")));
+        }
+        table.push((key, value));
+    }
+    Ok(tables)
+}
+
+//解析行首的键和等号，返回键和等号之后的内容
+fn toml_key(line: &str) -> Option<(String, &str)> {
+    let (key, rest) = if line.starts_with(['"', '\'']) {
+        toml_value(line).ok()?
+    } else {
+        let end = line
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(line.len());
+        (line[..end].to_string(), &line[end..])
+    };
+    let rest = rest.trim_start().strip_prefix('=')?;
+    (!key.is_empty()).then_some((key, rest))
+}
+
+//解析一个值，返回值和其后的内容
+fn toml_value(text: &str) -> Result<(String, &str), String> {
+    if text.starts_with("\"\"\"") || text.starts_with("'''") {
+        return Err("multi-line strings are not supported".to_string());
+    }
+    if let Some(literal) = text.strip_prefix('\'') {
+        let end = literal.find('\'').ok_or("unterminated string")?;
+        return Ok((literal[..end].to_string(), &literal[end + 1..]));
+    }
+    if let Some(basic) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = basic.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((value, &basic[i + 1..])),
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('b') => '\u{8}',
+                        Some('t') => '\t',
+                        Some('n') => '\n',
+                        Some('f') => '\u{c}',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(unicode @ ('u' | 'U')) => {
+                            let len = if unicode == 'u' { 4 } else { 8 };
+                            let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .filter(|_| hex.len() == len)
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("invalid escape `\\{unicode}{hex}`"))?
+                        }
+                        _ => return Err("invalid escape".to_string()),
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_')))
+        .unwrap_or(text.len());
+    let value = &text[..end];
+    let is_number = value.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-');
+    if value == "true" || value == "false" || (is_number && !value.is_empty()) {
+        Ok((value.to_string(), &text[end..]))
+    } else {
+        Err("only strings, booleans and numbers are supported as values".to_string())
+    }
+}
+
+#[cfg(test)]
+mod test_overlay {
+    use super::*;
+    use crate::{
+        BROWSER, BROWSER_TYPE, BrowsCapField, Fallback, IS_CRAWLER, PLATFORM, UserAgentParser,
+    };
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+    const APP: &str = "ExampleApp/3.1 (Linux; Tizen 4.0)";
+
+    fn build(overlay: RuleOverlay) -> UserAgentParser {
+        let fields: Vec<&'static BrowsCapField> = vec![&IS_CRAWLER];
+        let (parser, report) = UserAgentParser::builder()
+            .fields(fields)
+            .overlay(overlay)
+            .build_with_report()
+            .unwrap();
+        assert_eq!(
+            report.records_read,
+            report.rules_created + report.skipped.len() + report.suppressed
+        );
+        parser
+    }
+
+    #[test]
+    fn test_priority() {
+        let plain = build(RuleOverlay::new());
+        let pattern = plain.matched_pattern(IPHONE).unwrap();
+        assert_eq!(plain.parse_outcome(APP).fallback, Some(Fallback::Unmatched));
+        assert_eq!(plain.matched_pattern("Foo (iPhone)").unwrap(), "*iphone*");
+
+        let overlay = RuleOverlay::new()
+            .add(
+                "*Tizen*",
+                RulePriority::Ordered,
+                &[(&BROWSER, "Tizen Browser")],
+            )
+            .add("*Linux*", RulePriority::After, &[(&BROWSER, "Late")])
+            .add(
+                "*iPhone*",
+                RulePriority::Ordered,
+                &[(&BROWSER, "Duplicate")],
+            );
+        let parser = build(overlay);
+        assert_eq!(parser.parse(APP).get_browser(), Some("Tizen Browser"));
+        assert_eq!(parser.parse("Foo (Linux)").get_browser(), Some("Late"));
+        assert_eq!(parser.matched_pattern(IPHONE).unwrap(), pattern);
+        // 与browscap的rule重复时browscap的rule在前
+        assert_eq!(
+            parser.parse("Foo (iPhone)").get_browser(),
+            plain.parse("Foo (iPhone)").get_browser()
+        );
+
+        let overlay = RuleOverlay::new()
+            .add("*Tizen*", RulePriority::After, &[(&BROWSER, "Late")])
+            .add(
+                "ExampleApp/*Tizen*",
+                RulePriority::Before,
+                &[(&BROWSER, "ExampleApp"), (&PLATFORM, "Tizen")],
+            )
+            .add("*iPhone*", RulePriority::Before, &[(&BROWSER, "Early")]);
+        let parser = build(overlay);
+        let app = parser.parse(APP);
+        assert_eq!(app.get_browser(), Some("ExampleApp"));
+        assert_eq!(app.get_platform(), Some("Tizen"));
+        assert_eq!(app.get_browser_type(), Some("Unknown"));
+        assert_eq!(parser.matched_pattern(APP).unwrap(), "exampleapp/*tizen*");
+        assert_eq!(parser.parse(IPHONE).get_browser(), Some("Early"));
+        assert_eq!(parser.parse("Foo (iPhone)").get_browser(), Some("Early"));
+    }
+
+    #[test]
+    fn test_replace_and_suppress() {
+        let plain = build(RuleOverlay::new());
+        let pattern = plain.matched_pattern(IPHONE).unwrap();
+        let original = plain.parse(IPHONE);
+
+        let parser = build(
+            RuleOverlay::new().replace_values(&pattern.to_uppercase(), &[(&BROWSER, "Safari")]),
+        );
+        let replaced = parser.parse(IPHONE);
+        assert_eq!(parser.matched_pattern(IPHONE).unwrap(), pattern);
+        assert_eq!(replaced.get_browser(), Some("Safari"));
+        assert_eq!(replaced.get_platform(), original.get_platform());
+        assert_eq!(
+            replaced.get_value(&BROWSER_TYPE),
+            original.get_value(&BROWSER_TYPE)
+        );
+
+        let parser = build(RuleOverlay::new().suppress(&pattern));
+        assert_ne!(
+            parser.matched_pattern(IPHONE).as_deref(),
+            Some(pattern.as_str())
+        );
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let csv = "Pattern, Mode, Browser, is_crawler\n\
+                   ExampleApp/*, before, ExampleApp,\n\
+                   *partnerbot*, after, PartnerBot, true\n\
+                   *iphone*,, Phone,\n\
+                   *old*, suppress,,\n\
+                   *new*, replace, New,\n";
+        let overlay = RuleOverlay::from_csv(csv.as_bytes()).unwrap();
+        let rules: Vec<(&str, RulePriority, usize)> = overlay
+            .rules()
+            .iter()
+            .map(|rule| (rule.pattern.as_str(), rule.priority, rule.values.len()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("ExampleApp/*", RulePriority::Before, 1),
+                ("*partnerbot*", RulePriority::After, 2),
+                ("*iphone*", RulePriority::Ordered, 1),
+            ]
+        );
+        assert_eq!(overlay.override_for("*old*"), Some(&None));
+        assert!(matches!(overlay.override_for("*new*"), Some(Some(values)) if values.len() == 1));
+
+        for (csv, message) in [
+            ("browser\nx\n", "no `pattern` column"),
+            ("pattern,colour\nx,red\n", "unknown column `colour`"),
+            ("pattern,mode\nx,first\n", "line 2: unknown mode `first`"),
+        ] {
+            let error = RuleOverlay::from_csv(csv.as_bytes()).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("invalid rule overlay: {message}")
+            );
+        }
+        let error = RuleOverlay::from_file("overlay.yaml").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid rule overlay: `overlay.yaml`: only CSV and TOML overlay files are supported"
+        );
+        let error = UserAgentParser::builder()
+            .overlay(RuleOverlay::new().add("*", RulePriority::Before, &[]))
+            .build()
+            .unwrap_err();
+        assert!(matches!(error, ParseError::Overlay(_)), "{error}");
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let toml = r#"# partner rules
+[[rule]]
+pattern = "ExampleApp/*"
+mode = "before"
+Browser = "ExampleApp"   # shown in reports
+is_crawler = ""
+
+[[ rule ]]
+"pattern" = '*partnerbot*'
+mode = "after"
+browser = "Partner\u0042ot \"1\""
+is_crawler = true
+
+[[rule]]
+pattern = "*iphone*"
+browser_major_version = 10
+
+[[rule]]
+pattern = "*old*"
+mode = "suppress"
+"#;
+        let overlay = RuleOverlay::from_toml(toml.as_bytes()).unwrap();
+        let rules: Vec<(&str, RulePriority, Vec<&str>)> = overlay
+            .rules()
+            .iter()
+            .map(|rule| {
+                let values = rule.values.iter().map(|(_, value)| &**value).collect();
+                (rule.pattern.as_str(), rule.priority, values)
+            })
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("ExampleApp/*", RulePriority::Before, vec!["ExampleApp"]),
+                ("*partnerbot*", RulePriority::After, vec!["PartnerBot \"1\"", "true"]),
+                ("*iphone*", RulePriority::Ordered, vec!["10"]),
+            ]
+        );
+        assert_eq!(overlay.override_for("*old*"), Some(&None));
+        let path = std::env::temp_dir().join(format!("overlay-{}.toml", std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+        let from_file = RuleOverlay::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap().rules().len(), 3);
+
+        for (toml, message) in [
+            ("[rule]\npattern = \"x\"\n", "line 1: only [[rule]] tables are supported"),
+            ("pattern = \"x\"\n", "line 1: key outside a [[rule]] table"),
+            ("[[rule]]\nbrowser = \"x\"\n", "line 1: rule without `pattern`"),
+            ("[[rule]]\npattern = \"x\"\ncolour = \"red\"\n", "line 1: unknown key `colour`"),
+            ("[[rule]]\npattern = \"x\"\nmode = \"first\"\n", "line 1: unknown mode `first`"),
+            (
+                "[[rule]]\npattern = [\"x\"]\n",
+                "line 2: only strings, booleans and numbers are supported as values",
+            ),
+            ("[[rule]]\npattern = \"x\"\npattern = \"y\"\n", "line 3: duplicate key `pattern`"),
+            ("[[rule]]\npattern = \"x\" y\n", "line 2: unexpected text after the value"),
+            ("[[rule]]\npattern = \"x\n", "line 2: unterminated string"),
+        ] {
+            let error = RuleOverlay::from_toml(toml.as_bytes()).unwrap_err();
+            assert_eq!(error.to_string(), format!("invalid rule overlay: {message}"), "{toml}");
+        }
+    }
+}
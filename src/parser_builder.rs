@@ -0,0 +1,209 @@
+use crate::data_path;
+use crate::error::ParseError;
+use crate::file_parser::{self, FileParser};
+use crate::load_report::LoadContext;
+use crate::{
+    BrowsCapField, Capabilities, Fallback, FieldSet, LoadProgress, LoadReport, MatchEngine,
+    ParserBuilder, ParserOptions, Predicate, RuleOverlay, UserAgentParser,
+};
+use log::debug;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::time::Instant;
+
+impl UserAgentParser {
+    /// Starts configuring a parser. Without further settings it loads the default fields from
+    /// the discovered browscap file like [`crate::load_parser_default`].
+    pub fn builder() -> ParserBuilder {
+        ParserBuilder::new()
+    }
+}
+
+impl Default for ParserBuilder {
+    fn default() -> Self {
+        ParserBuilder::new()
+    }
+}
+
+impl ParserBuilder {
+    pub fn new() -> ParserBuilder {
+        ParserBuilder {
+            my_fields: FieldSet::DEFAULT,
+            my_file: None,
+            my_fallbacks: Vec::new(),
+            my_options: ParserOptions::default(),
+            my_progress: None,
+            my_overlay: RuleOverlay::default(),
+            my_rule_filters: Vec::new(),
+        }
+    }
+
+    /// Fields to load in addition to the default ones, a [`FieldSet`] such as
+    /// [`FieldSet::STANDARD`] or a list of fields.
+    pub fn fields(mut self, fields: impl Into<FieldSet>) -> ParserBuilder {
+        self.my_fields = fields.into();
+        self
+    }
+
+    /// The browscap file to load: a CSV file or, with an `.ini` extension, an INI file in the
+    /// `php_browscap.ini` format whose sections inherit from their `Parent`. Without it the file
+    /// is searched for in order at `$BROWSCAP_FILE`, `browscap/browscap_sorted.csv` under
+    /// `$XDG_DATA_HOME` and each of `$XDG_DATA_DIRS`, the [`ParserBuilder::fallback`] paths and
+    /// finally `browscap_sorted.csv` in the current directory.
+    pub fn file(mut self, file_name: impl Into<PathBuf>) -> ParserBuilder {
+        self.my_file = Some(file_name.into());
+        self
+    }
+
+    /// Adds a location to try when no [`ParserBuilder::file`] is set and the file is not in any
+    /// of the environment-based locations. Fallbacks are tried in the order they were added.
+    pub fn fallback(mut self, file_name: impl Into<PathBuf>) -> ParserBuilder {
+        self.my_fallbacks.push(file_name.into());
+        self
+    }
+
+    /// Whether candidate rules are narrowed down with a trie over every rule's fixed prefix and
+    /// postfix before matching, instead of scanning every rule that passes the filters (the
+    /// default). The index pays off when the dataset has many distinct prefixes; compare both
+    /// with the `parse_bench` benchmark on your data.
+    pub fn rule_index(mut self, enabled: bool) -> ParserBuilder {
+        self.my_options.rule_index = enabled;
+        self
+    }
+
+    /// The engine used to find the matching rule, see [`MatchEngine`]. The rule index only
+    /// applies to [`MatchEngine::Rules`].
+    pub fn engine(mut self, engine: MatchEngine) -> ParserBuilder {
+        self.my_options.engine = engine;
+        self
+    }
+
+    /// Whether sparse filter masks, those with at most one rule in 64, are stored as Roaring
+    /// bitmaps. The representation is chosen per mask and the dense masks stay bit vectors;
+    /// while parsing, the Roaring masks are combined with bitmap AND/OR before being applied to
+    /// the dense result. Off by default: on the bundled browscap file it saves a few percent of
+    /// the filter memory and parses no faster.
+    pub fn compressed_masks(mut self, enabled: bool) -> ParserBuilder {
+        self.my_options.compressed_masks = enabled;
+        self
+    }
+
+    /// What [`crate::Capabilities::get_value`] returns for fields without a value, `"Unknown"`
+    /// by default.
+    pub fn unknown_value(mut self, placeholder: impl Into<Box<str>>) -> ParserBuilder {
+        self.my_options.unknown_value = placeholder.into();
+        self
+    }
+
+    /// Capabilities returned for `fallback` instead of the built-in ones. Fields missing from
+    /// `values` have no value and fields that are not loaded are ignored. By default unmatched
+    /// user agents get `"Default Browser"` as browser and browser type, and empty and too long
+    /// ones have no value for any field.
+    pub fn fallback_capabilities(
+        mut self,
+        fallback: Fallback,
+        values: &[(&'static BrowsCapField, &str)],
+    ) -> ParserBuilder {
+        let values = values.iter().map(|(field, value)| (*field, (*value).into())).collect();
+        self.my_options.fallbacks[fallback as usize] = Some(values);
+        self
+    }
+
+    /// User agents longer than `max_length` bytes are not matched and get the
+    /// [`Fallback::TooLong`] capabilities. Unlimited by default.
+    pub fn max_length(mut self, max_length: usize) -> ParserBuilder {
+        self.my_options.max_length = Some(max_length);
+        self
+    }
+
+    /// Called with the current [`LoadProgress`] when each [`crate::LoadPhase`] starts and
+    /// periodically while reading records. Returning `false` cancels the load, which then fails
+    /// with [`ParseError::Cancelled`].
+    pub fn progress(
+        mut self,
+        callback: impl FnMut(&LoadProgress) -> bool + Send + 'static,
+    ) -> ParserBuilder {
+        self.my_progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Changes the browscap rules as described by `overlay`. Overlays given in several calls are
+    /// merged with [`RuleOverlay::merge`].
+    pub fn overlay(mut self, overlay: RuleOverlay) -> ParserBuilder {
+        self.my_overlay = std::mem::take(&mut self.my_overlay).merge(overlay);
+        self
+    }
+
+    /// Keeps only the browscap rules whose capabilities `predicate` accepts. The others are
+    /// dropped while reading, before any memory is spent on their patterns, which shrinks the
+    /// parser for services that only care about part of the traffic. The predicate sees the
+    /// fields set with [`ParserBuilder::fields`], so those it tests have to be loaded, and is
+    /// called once for each distinct set of values. Predicates given in several calls all have to
+    /// accept a rule. Rules added by the [`ParserBuilder::overlay`] are always kept.
+    pub fn rule_filter(
+        mut self,
+        predicate: impl Predicate<Capabilities> + Send + 'static,
+    ) -> ParserBuilder {
+        self.my_rule_filters.push(Box::new(predicate));
+        self
+    }
+
+    pub fn build(self) -> Result<UserAgentParser, ParseError> {
+        self.build_with_report().map(|(parser, _)| parser)
+    }
+
+    /// Like [`ParserBuilder::build`], also returning what happened during the load.
+    pub fn build_with_report(mut self) -> Result<(UserAgentParser, LoadReport), ParseError> {
+        let timer = Instant::now();
+        let path = data_path::resolve(self.my_file.as_deref(), &self.my_fallbacks)?;
+        let io_error = |source| ParseError::Io {
+            path: path.clone(),
+            source,
+        };
+        let file = File::open(&path).map_err(io_error)?;
+        let total_bytes = file.metadata().ok().map(|metadata| metadata.len());
+        let mut context = LoadContext::new(self.my_progress.as_deref_mut(), total_bytes);
+        let merged_unique_fields = crate::merge_fields(self.my_fields);
+        let overlay = std::mem::take(&mut self.my_overlay);
+        let mut file_parser = FileParser::new(merged_unique_fields, overlay);
+        if !self.my_rule_filters.is_empty() {
+            let predicates = std::mem::take(&mut self.my_rule_filters);
+            file_parser.set_rule_filter(predicates, &self.my_options.unknown_value);
+        }
+        if data_path::is_ini(&path) {
+            let text = io::read_to_string(file).map_err(io_error)?;
+            file_parser.parse_ini(&text, &mut context)?;
+        } else {
+            file_parser.parse(BufReader::new(file), &mut context)?;
+        }
+        context.report.read_time = timer.elapsed();
+        let parser = file_parser::create_agent_parser(file_parser, &self.my_options, &mut context)?;
+        let mut report = context.report;
+        report.file = path;
+        report.total_time = timer.elapsed();
+        debug!(
+            "loaded {} rules from {} records in {:?}, {} skipped",
+            report.rules_created,
+            report.records_read,
+            report.total_time,
+            report.skipped.len()
+        );
+        Ok((parser, report))
+    }
+}
+
+impl fmt::Debug for ParserBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParserBuilder")
+            .field("my_fields", &self.my_fields)
+            .field("my_file", &self.my_file)
+            .field("my_fallbacks", &self.my_fallbacks)
+            .field("my_options", &self.my_options)
+            .field("my_progress", &self.my_progress.is_some())
+            .field("my_overlay", &self.my_overlay)
+            .field("my_rule_filters", &self.my_rule_filters.len())
+            .finish()
+    }
+}
@@ -0,0 +1,173 @@
+use crate::literal::Literal;
+use crate::rule::Rule;
+use regex::bytes::{RegexSet, RegexSetBuilder};
+
+//编译后的自动机可能很大，默认的10MB上限装不下完整的browscap数据
+const SIZE_LIMIT: usize = 1 << 30;
+const DFA_SIZE_LIMIT: usize = 1 << 28;
+
+/*
+    把所有rule的pattern编译进同一个RegexSet，一次扫描UA就能得到所有匹配的rule。
+    rule已按优先级排好序，所以下标最小的匹配就是结果
+*/
+#[derive(Debug, Clone)]
+pub struct PatternSet {
+    my_set: RegexSet,
+}
+
+impl PatternSet {
+    pub(crate) fn new(rules: &[Rule]) -> Result<PatternSet, regex::Error> {
+        let my_set = RegexSetBuilder::new(rules.iter().map(rule_regex))
+            .size_limit(SIZE_LIMIT)
+            .dfa_size_limit(DFA_SIZE_LIMIT)
+            .build()?;
+        Ok(PatternSet { my_set })
+    }
+
+    /// Returns the index of the highest-priority rule matching the ASCII-lower-cased `value`.
+    pub(crate) fn find(&self, value: &[u8]) -> Option<usize> {
+        self.my_set.matches(value).iter().next()
+    }
+}
+
+/*
+    prefix*suffix1*suffix2*postfix 转成 ^prefix.*suffix1.*suffix2.*postfix$。
+    没有*的pattern只有prefix，需要整串相等。?匹配任意一个字节
+*/
+fn rule_regex(rule: &Rule) -> String {
+    let mut result = String::from("(?s-u)^");
+    if let Some(prefix) = rule.get_prefix() {
+        push_literal(&mut result, &prefix);
+    }
+    if let Some(suffixes) = rule.get_suffixes() {
+        result.push_str(".*");
+        for suffix in suffixes {
+            push_literal(&mut result, suffix);
+            result.push_str(".*");
+        }
+    }
+    if let Some(postfix) = rule.get_postfix() {
+        push_literal(&mut result, postfix);
+    }
+    result.push('$');
+    result
+}
+
+fn push_literal(result: &mut String, literal: &Literal) {
+    for (i, part) in literal.get_string().split('?').enumerate() {
+        if i > 0 {
+            result.push('.');
+        }
+        result.push_str(&regex::escape(part));
+    }
+}
+
+#[cfg(test)]
+mod test_pattern_set {
+    use super::*;
+    use crate::literal::LiteralArena;
+    use crate::searchable_string::SearchableString;
+    use crate::{MatchEngine, UserAgentParser};
+
+    fn create_rules(patterns: &[&str]) -> Vec<Rule> {
+        let mut literals = LiteralArena::new();
+        patterns
+            .iter()
+            .map(|pattern| {
+                crate::rule::create_rule(pattern.to_string(), 0, &mut literals).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rule_regex() {
+        let rules = create_rules(&["abc", "abc*", "*a?c*d.e*", "a*(b)"]);
+        let regexes: Vec<String> = rules.iter().map(rule_regex).collect();
+        assert_eq!(
+            regexes,
+            vec![
+                "(?s-u)^abc$",
+                "(?s-u)^abc.*$",
+                "(?s-u)^.*a.c.*d\\.e.*$",
+                "(?s-u)^a.*\\(b\\)$",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_same_as_rules() {
+        let mut rules = create_rules(&[
+            "mozilla/5.0 (*android*chrome/*",
+            "mozilla/5.0 (*iphone*safari/*",
+            "mozilla/?.0 (*",
+            "*googlebot*",
+            "*bot",
+            "opera/9.?? *",
+            "curl/7.0",
+            "caf??/*",
+        ]);
+        crate::user_agent_parser::get_ordered_rules(&mut rules);
+        let pattern_set = PatternSet::new(&rules).unwrap();
+        for user_agent in [
+            "Mozilla/5.0 (Linux; Android 9) Chrome/70 Mobile",
+            "Mozilla/5.0 (iPhone; CPU OS 12) Safari/604",
+            "Mozilla/4.0 (compatible)",
+            "Googlebot/2.1",
+            "somebot",
+            "Opera/9.80 (Windows)",
+            "curl/7.0",
+            "curl/7.01",
+            "Café/1.0",
+            "Cafe/1.0",
+        ] {
+            let search_string = SearchableString::new(user_agent);
+            let expected = rules
+                .iter()
+                .position(|rule| rule.matches(&mut SearchableString::new(user_agent)));
+            let found = pattern_set.find(search_string.as_bytes());
+            assert_eq!(found, expected, "{user_agent}");
+        }
+        // 两个engine的?都匹配一个字节
+        let cafe = SearchableString::new("Café/1.0");
+        assert_eq!(rules[pattern_set.find(cafe.as_bytes()).unwrap()].get_pattern(), "caf??/*");
+    }
+
+    #[test]
+    fn test_corpus_same_as_rules() {
+        let parser = UserAgentParser::builder().build().unwrap();
+        let regex_set = UserAgentParser::builder()
+            .engine(MatchEngine::RegexSet)
+            .build()
+            .unwrap();
+        let user_agents = std::fs::read_to_string("useragents.txt").unwrap();
+        let mut count = 0;
+        // 未优化的regex-automata很慢，只取每10个UA中的一个；Rules引擎在tests中跑完整的文件
+        let sample = user_agents.lines().filter(|line| !line.starts_with('#')).step_by(10);
+        for line in sample {
+            // 前5列是期望的browser、主版本、platform、platform版本和设备类型，第6列是User-Agent
+            let columns: Vec<&str> = line.split("    ").collect();
+            if columns.len() < 6 {
+                continue;
+            }
+            let user_agent = columns[5];
+            for engine in [&parser, &regex_set] {
+                let capabilities = engine.parse(user_agent);
+                let found = [
+                    capabilities.get_browser(),
+                    capabilities.get_browser_major_version(),
+                    capabilities.get_platform(),
+                    capabilities.get_platform_version(),
+                    capabilities.get_device_type(),
+                ];
+                assert_eq!(found.map(Option::unwrap), columns[..5], "{user_agent}");
+            }
+            assert_eq!(
+                regex_set.matched_pattern(user_agent),
+                parser.matched_pattern(user_agent),
+                "{user_agent}"
+            );
+            count += 1;
+        }
+        assert!(count > 100);
+    }
+}
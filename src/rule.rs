@@ -1,19 +1,20 @@
-use crate::capabilities::init_wild_card_capa;
 use crate::error::ParseError;
-use crate::literal::Literal;
+use crate::literal::{Literal, LiteralArena};
 use crate::searchable_string::SearchableString;
-use crate::{BrowsCapField, Capabilities, searchable_string};
+use crate::searchable_string;
 use regex::Regex;
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::sync::Arc;
 use ustr::Ustr;
 
+#[derive(Clone)]
 pub struct Rule {
     my_prefix: Option<Arc<Literal>>,
     my_suffixes: Option<Vec<Arc<Literal>>>,
     my_postfix: Option<Arc<Literal>>,
     pattern_len: u32,
-    my_capabilities: Arc<Capabilities>,
+    my_capabilities: u32,
 }
 impl Debug for Rule {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -31,7 +32,7 @@ impl Rule {
         suffixes: Option<Vec<Arc<Literal>>>,
         postfix: Option<Arc<Literal>>,
         pattern_len: u32,
-        capabilities: Arc<Capabilities>,
+        capabilities: u32,
     ) -> Self {
         Rule {
             my_prefix: prefix,
@@ -46,42 +47,43 @@ impl Rule {
         self.pattern_len
     }
 
-    pub fn matches(&self, value: &mut SearchableString) -> bool {
-        let start: i32;
-        if self.my_prefix.is_none() {
-            start = 0;
-        } else if searchable_string::starts_with(value, &*self.my_prefix.as_ref().unwrap().clone())
-        {
-            start = self.my_prefix.as_ref().unwrap().get_length() as i32;
-        } else {
-            return false;
-        }
+    //rule自身之外占用的堆内存
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.my_suffixes
+            .as_ref()
+            .map_or(0, |suffixes| suffixes.capacity() * size_of::<Arc<Literal>>())
+    }
 
-        let end: i32;
-        if self.my_postfix.is_none() {
-            end = value.get_size() as i32 - 1;
-        } else if value.ends_with(self.my_postfix.as_ref().unwrap()) {
-            end =
-                value.get_size() as i32 - 1 - self.my_postfix.as_ref().unwrap().get_length() as i32;
-        } else {
-            return false;
-        }
-        let x = self.check_wild_cards(value, self.my_suffixes.clone(), start, end);
+    pub fn matches(&self, value: &mut SearchableString) -> bool {
+        let start: i32 = match &self.my_prefix {
+            None => 0,
+            Some(prefix) if searchable_string::starts_with(value, prefix) => {
+                prefix.get_length() as i32
+            }
+            Some(_) => return false,
+        };
 
-        x
+        let end: i32 = match &self.my_postfix {
+            None => value.get_size() as i32 - 1,
+            Some(postfix) if value.ends_with(postfix) => {
+                value.get_size() as i32 - 1 - postfix.get_length() as i32
+            }
+            Some(_) => return false,
+        };
+        self.check_wild_cards(value, self.my_suffixes.as_deref(), start, end)
     }
 
     fn check_wild_cards(
         &self,
         value: &mut SearchableString,
-        suffixes: Option<Vec<Arc<Literal>>>,
+        suffixes: Option<&[Arc<Literal>]>,
         start: i32,
         end: i32,
     ) -> bool {
         match suffixes {
             None => {
                 // No wildcards
-                return start == end + 1;
+                start == end + 1
             }
             Some(suffix_list) => {
                 // One wildcard
@@ -91,7 +93,7 @@ impl Rule {
 
                 let mut from = start;
                 for suffix in suffix_list {
-                    let match_pos = Self::check_wild_card(value, &suffix, from);
+                    let match_pos = Self::check_wild_card(value, suffix, from);
                     if match_pos == -1i32 {
                         return false;
                     }
@@ -108,7 +110,7 @@ impl Rule {
 
     fn check_wild_card(value: &mut SearchableString, suffix: &Literal, start: i32) -> i32 {
         let x = value.get_indices(suffix);
-        for i in x {
+        for i in x.iter() {
             let index = *i as i32;
             if index >= start {
                 return index;
@@ -121,12 +123,8 @@ impl Rule {
         // 精确计算容量
         let capacity = self.my_prefix.as_ref().map_or(0, |p| p.my_string.len())
             + self.my_suffixes.as_ref().map_or(0, |s| {
-                if s.is_empty() {
-                    0
-                } else {
-                    // 后缀字符串总长度 + 每个后缀前后的星号
-                    s.iter().map(|sub| sub.my_string.len()).sum::<usize>() + s.len() + 1
-                }
+                // 后缀字符串总长度 + 每个后缀前后的星号
+                s.iter().map(|sub| sub.my_string.len()).sum::<usize>() + s.len() + 1
             })
             + self.my_postfix.as_ref().map_or(0, |p| p.my_string.len());
 
@@ -136,13 +134,12 @@ impl Rule {
             result.push_str(&prefix.my_string);
         }
 
+        // suffixes为Some时pattern里至少有一个*，为空时是prefix*postfix
         if let Some(suffixes) = &self.my_suffixes {
-            if !suffixes.is_empty() {
+            result.push('*');
+            for sub in suffixes {
+                result.push_str(&sub.my_string);
                 result.push('*');
-                for sub in suffixes {
-                    result.push_str(&sub.my_string);
-                    result.push('*');
-                }
             }
         }
 
@@ -152,19 +149,62 @@ impl Rule {
         result
     }
 
+    //pattern的结构是否相同，literal来自不同的LiteralArena时也可以比较
+    pub(crate) fn same_pattern(&self, other: &Rule) -> bool {
+        fn same(a: &Literal, b: &Literal) -> bool {
+            a.my_string == b.my_string
+        }
+        self.pattern_len == other.pattern_len
+            && match (&self.my_prefix, &other.my_prefix) {
+                (None, None) => true,
+                (Some(a), Some(b)) => same(a, b),
+                _ => false,
+            }
+            && match (&self.my_suffixes, &other.my_suffixes) {
+                (None, None) => true,
+                (Some(a), Some(b)) => {
+                    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+                }
+                _ => false,
+            }
+            && match (&self.my_postfix, &other.my_postfix) {
+                (None, None) => true,
+                (Some(a), Some(b)) => same(a, b),
+                _ => false,
+            }
+    }
+
     pub fn get_prefix(&self) -> Option<Arc<Literal>> {
         self.my_prefix.clone()
     }
 
-    pub fn get_capabilities(&self) -> &Capabilities {
-        &self.my_capabilities
+    pub fn get_postfix(&self) -> Option<&Arc<Literal>> {
+        self.my_postfix.as_ref()
+    }
+
+    //None表示pattern里没有*
+    pub fn get_suffixes(&self) -> Option<&[Arc<Literal>]> {
+        self.my_suffixes.as_deref()
+    }
+
+    //按prefix、suffixes、postfix的顺序返回所有literal
+    pub fn get_literals(&self) -> impl Iterator<Item = &Arc<Literal>> {
+        self.my_prefix
+            .iter()
+            .chain(self.my_suffixes.iter().flatten())
+            .chain(self.my_postfix.iter())
+    }
+
+    //在parser的CapabilityTable中的行号
+    pub fn get_capabilities(&self) -> u32 {
+        self.my_capabilities
     }
 
     pub fn requires(&self, value: Ustr) -> bool {
-        if self.my_prefix.as_ref().map_or(false, |item|item.requires(value)) {
+        if self.my_prefix.as_ref().is_some_and(|item| item.requires(value)) {
             return true;
         }
-        if self.my_postfix.as_ref().map_or(false, |item|item.requires(value)) {
+        if self.my_postfix.as_ref().is_some_and(|item| item.requires(value)) {
             return true;
         }
 
@@ -182,7 +222,11 @@ impl Rule {
 
 }
 
-pub fn create_rule(pattern: String, capabilities: Arc<Capabilities>) -> Result<Rule, ParseError> {
+pub fn create_rule(
+    pattern: String,
+    capabilities: u32,
+    literals: &mut LiteralArena,
+) -> Result<Rule, ParseError> {
     let parts = get_parts(&pattern);
     if parts.is_empty() {
         return Err(ParseError::EmptyPattern);
@@ -192,7 +236,7 @@ pub fn create_rule(pattern: String, capabilities: Arc<Capabilities>) -> Result<R
         if "*" == *first {
             return Err(ParseError::FixedPattern);
         }
-        let option = crate::literal::get_literal(first);
+        let option = literals.get_literal(first);
         return Ok(Rule::new(
             Some(option),
             None,
@@ -206,31 +250,31 @@ pub fn create_rule(pattern: String, capabilities: Arc<Capabilities>) -> Result<R
     let last = &parts[parts.len() - 1];
 
     // 预先计算需要的值
-    let has_prefix = !("*" == *first);
-    let has_postfix = !("*" == *last);
+    let has_prefix = "*" != *first;
+    let has_postfix = "*" != *last;
     let mut middle_parts: Vec<&str> = parts[if has_prefix { 1 } else { 0 }..if has_postfix {
         parts.len() - 1
     } else {
         parts.len()
     }]
         .to_vec();
-    middle_parts.retain(|suffix| !(*suffix == "*"));
+    middle_parts.retain(|suffix| *suffix != "*");
 
     let prefix = if has_prefix {
-        Some(crate::literal::get_literal(first))
+        Some(literals.get_literal(first))
     } else {
         None
     };
 
     let postfix = if has_postfix {
-        Some(crate::literal::get_literal(last))
+        Some(literals.get_literal(last))
     } else {
         None
     };
 
     let mut suffix_array: Vec<Arc<Literal>> = Vec::new();
     for part in middle_parts {
-        suffix_array.push(crate::literal::get_literal(part));
+        suffix_array.push(literals.get_literal(part));
     }
 
     Ok(Rule::new(
@@ -268,8 +312,23 @@ fn get_parts(pattern: &str) -> Vec<&str> {
     parts
 }
 
+/*
+    同样长的rule按pattern的字节排序，*和?排在所有字面字节之后：
+    前面相同时先尝试字面更具体的pattern
+*/
+pub(crate) fn compare_patterns(a: &str, b: &str) -> Ordering {
+    fn key(byte: u8) -> u16 {
+        match byte {
+            b'*' | b'?' => 0x100 | u16::from(byte),
+            _ => u16::from(byte),
+        }
+    }
+    a.bytes().map(key).cmp(b.bytes().map(key))
+}
+
 pub(crate) fn normalize_pattern(pattern: &str) -> String {
-    let pattern = pattern.to_lowercase();
+    // UA只做ASCII小写转换，pattern保持一致
+    let pattern = pattern.to_ascii_lowercase();
     if pattern.contains("**") {
         let re = Regex::new(r"\*+").unwrap();
         re.replace_all(&pattern, "*").to_string()
@@ -277,13 +336,3 @@ pub(crate) fn normalize_pattern(pattern: &str) -> String {
         pattern.to_string()
     }
 }
-
-pub fn get_wild_card_rule(fields: &Vec<&'static BrowsCapField>) -> Rule {
-    Rule::new(
-        None,
-        Some(Vec::new()),
-        None,
-        "*".len() as u32,
-        init_wild_card_capa(fields),
-    )
-}
@@ -0,0 +1,440 @@
+use crate::filter;
+use crate::rule::Rule;
+use crate::searchable_string::SearchableString;
+use bitvec::vec::BitVec;
+
+const NO_NODE: u32 = 0;
+const ROOT: u32 = 0;
+
+/*
+    rule索引：
+    前缀树以每条rule的my_prefix为键，解析时从UA开头走一遍即可得到所有匹配的前缀，
+    只有前缀匹配（或没有前缀）的rule才会被检查；
+    后缀树以反转后的my_postfix为键，从UA结尾往前走，得到所有匹配的后缀。
+    ?在树中是一条可以匹配任意字节的边。
+*/
+#[derive(Debug, Clone)]
+pub struct RuleIndex {
+    my_prefix_trie: LiteralTrie,
+    my_postfix_trie: LiteralTrie,
+    //每个前缀终点对应的rule集合
+    my_prefix_rules: Vec<RuleSet>,
+    my_unprefixed_rules: BitVec,
+    //后缀树已分配的终点个数，前缀树的终点个数就是my_prefix_rules的长度
+    my_postfix_terminals: u32,
+}
+
+/*
+    rule多的前缀用bitvec保存，做按字或运算；rule少的前缀只保存下标，逐个置位。
+    当下标列表占用的内存不小于bitvec时才改用bitvec
+*/
+#[derive(Debug, Clone)]
+enum RuleSet {
+    Dense(BitVec),
+    Sparse(Vec<u32>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct LiteralTrie {
+    my_nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: Vec<(u8, u32)>,
+    wildcard: u32,
+    //(literal下标, 终点编号)
+    terminal: Option<(usize, u32)>,
+}
+
+/// Working memory of the rule index, kept inside [`crate::Scratch`].
+#[derive(Debug, Default)]
+pub struct IndexScratch {
+    my_active: Vec<u32>,
+    my_next: Vec<u32>,
+    //(literal下标, 终点编号)
+    my_found: Vec<(u32, u32)>,
+    my_candidates: BitVec,
+}
+
+impl LiteralTrie {
+    fn new() -> LiteralTrie {
+        LiteralTrie {
+            my_nodes: vec![TrieNode::default()],
+        }
+    }
+
+    //返回终点编号，相同的literal共用一个终点
+    fn insert(
+        &mut self,
+        bytes: impl Iterator<Item = u8>,
+        literal_index: usize,
+        next_terminal: &mut u32,
+    ) -> u32 {
+        let mut node = ROOT;
+        for byte in bytes {
+            let existing = if byte == b'?' {
+                self.my_nodes[node as usize].wildcard
+            } else {
+                let children = &self.my_nodes[node as usize].children;
+                match children.binary_search_by_key(&byte, |(b, _)| *b) {
+                    Ok(position) => children[position].1,
+                    Err(_) => NO_NODE,
+                }
+            };
+            node = if existing != NO_NODE {
+                existing
+            } else {
+                let created = self.my_nodes.len() as u32;
+                self.my_nodes.push(TrieNode::default());
+                let parent = &mut self.my_nodes[node as usize];
+                if byte == b'?' {
+                    parent.wildcard = created;
+                } else {
+                    let position = parent
+                        .children
+                        .binary_search_by_key(&byte, |(b, _)| *b)
+                        .unwrap_err();
+                    parent.children.insert(position, (byte, created));
+                }
+                created
+            };
+        }
+        let terminal = &mut self.my_nodes[node as usize].terminal;
+        match terminal {
+            Some((_, id)) => *id,
+            None => {
+                let id = *next_terminal;
+                *next_terminal += 1;
+                *terminal = Some((literal_index, id));
+                id
+            }
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.my_nodes.capacity() * size_of::<TrieNode>()
+            + self
+                .my_nodes
+                .iter()
+                .map(|node| node.children.capacity() * size_of::<(u8, u32)>())
+                .sum::<usize>()
+    }
+
+    //沿着bytes走，把经过的所有终点(literal下标, 终点编号)交给visit
+    fn walk(
+        &self,
+        bytes: impl Iterator<Item = u8>,
+        active: &mut Vec<u32>,
+        next: &mut Vec<u32>,
+        mut visit: impl FnMut(usize, u32),
+    ) {
+        active.clear();
+        active.push(ROOT);
+        for byte in bytes {
+            next.clear();
+            for node in active.iter() {
+                let node = &self.my_nodes[*node as usize];
+                if let Some((literal_index, terminal)) = node.terminal {
+                    visit(literal_index, terminal);
+                }
+                if let Ok(position) = node.children.binary_search_by_key(&byte, |(b, _)| *b) {
+                    next.push(node.children[position].1);
+                }
+                if node.wildcard != NO_NODE {
+                    next.push(node.wildcard);
+                }
+            }
+            std::mem::swap(active, next);
+            if active.is_empty() {
+                return;
+            }
+        }
+        for node in active.iter() {
+            if let Some((literal_index, terminal)) = self.my_nodes[*node as usize].terminal {
+                visit(literal_index, terminal);
+            }
+        }
+    }
+}
+
+impl RuleIndex {
+    pub(crate) fn new(rules: &[Rule]) -> RuleIndex {
+        let mut my_prefix_trie = LiteralTrie::new();
+        let mut my_postfix_trie = LiteralTrie::new();
+        let mut prefix_lists: Vec<Vec<u32>> = Vec::new();
+        let mut my_unprefixed_rules = BitVec::repeat(false, rules.len());
+        let mut prefix_terminals = 0;
+        let mut postfix_terminals = 0;
+        for (i, rule) in rules.iter().enumerate() {
+            match rule.get_prefix() {
+                Some(prefix) => {
+                    let terminal = my_prefix_trie.insert(
+                        prefix.get_string().bytes(),
+                        prefix.get_index(),
+                        &mut prefix_terminals,
+                    );
+                    if terminal as usize == prefix_lists.len() {
+                        prefix_lists.push(Vec::new());
+                    }
+                    prefix_lists[terminal as usize].push(i as u32);
+                }
+                None => my_unprefixed_rules.set(i, true),
+            }
+            if let Some(postfix) = rule.get_postfix() {
+                my_postfix_trie.insert(
+                    postfix.get_string().bytes().rev(),
+                    postfix.get_index(),
+                    &mut postfix_terminals,
+                );
+            }
+        }
+        let my_prefix_rules = prefix_lists
+            .into_iter()
+            .map(|list| {
+                if list.len() * u32::BITS as usize >= rules.len() {
+                    let mut mask = BitVec::repeat(false, rules.len());
+                    for i in list {
+                        mask.set(i as usize, true);
+                    }
+                    RuleSet::Dense(mask)
+                } else {
+                    RuleSet::Sparse(list)
+                }
+            })
+            .collect();
+        RuleIndex {
+            my_prefix_trie,
+            my_postfix_trie,
+            my_prefix_rules,
+            my_unprefixed_rules,
+            my_postfix_terminals: postfix_terminals,
+        }
+    }
+
+    /*
+        在position插入一条rule：其后rule的下标都加一，再把rule的前缀和后缀加入树中。
+        去掉rule时树中的literal保留，没有rule依赖的终点不影响结果
+    */
+    pub(crate) fn insert_rule(&mut self, position: usize, rule: &Rule) {
+        for rule_set in self.my_prefix_rules.iter_mut() {
+            match rule_set {
+                RuleSet::Dense(mask) => mask.insert(position, false),
+                RuleSet::Sparse(list) => list
+                    .iter_mut()
+                    .filter(|i| **i as usize >= position)
+                    .for_each(|i| *i += 1),
+            }
+        }
+        self.my_unprefixed_rules
+            .insert(position, rule.get_prefix().is_none());
+        if let Some(prefix) = rule.get_prefix() {
+            let mut prefix_terminals = self.my_prefix_rules.len() as u32;
+            let terminal = self.my_prefix_trie.insert(
+                prefix.get_string().bytes(),
+                prefix.get_index(),
+                &mut prefix_terminals,
+            );
+            if terminal as usize == self.my_prefix_rules.len() {
+                self.my_prefix_rules.push(RuleSet::Sparse(Vec::new()));
+            }
+            match &mut self.my_prefix_rules[terminal as usize] {
+                RuleSet::Dense(mask) => mask.set(position, true),
+                RuleSet::Sparse(list) => {
+                    let at = list.partition_point(|i| (*i as usize) < position);
+                    list.insert(at, position as u32);
+                }
+            }
+        }
+        if let Some(postfix) = rule.get_postfix() {
+            self.my_postfix_trie.insert(
+                postfix.get_string().bytes().rev(),
+                postfix.get_index(),
+                &mut self.my_postfix_terminals,
+            );
+        }
+    }
+
+    pub(crate) fn remove_rule(&mut self, position: usize) {
+        for rule_set in self.my_prefix_rules.iter_mut() {
+            match rule_set {
+                RuleSet::Dense(mask) => {
+                    mask.remove(position);
+                }
+                RuleSet::Sparse(list) => {
+                    list.retain(|i| *i as usize != position);
+                    list.iter_mut()
+                        .filter(|i| **i as usize > position)
+                        .for_each(|i| *i -= 1);
+                }
+            }
+        }
+        self.my_unprefixed_rules.remove(position);
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        let rule_sets = self
+            .my_prefix_rules
+            .iter()
+            .map(|rule_set| match rule_set {
+                RuleSet::Dense(mask) => size_of_val(mask.as_raw_slice()),
+                RuleSet::Sparse(list) => list.capacity() * size_of::<u32>(),
+            })
+            .sum::<usize>();
+        self.my_prefix_trie.memory_usage()
+            + self.my_postfix_trie.memory_usage()
+            + self.my_prefix_rules.capacity() * size_of::<RuleSet>()
+            + rule_sets
+            + size_of_val(self.my_unprefixed_rules.as_raw_slice())
+    }
+
+    /// Returns the first rule, in priority order, that passes the filters in `includes` and
+    /// matches the user agent.
+    pub(crate) fn find<'r>(
+        &self,
+        rules: &'r [Rule],
+        search_string: &mut SearchableString,
+        includes: &BitVec,
+        scratch: &mut IndexScratch,
+    ) -> Option<&'r Rule> {
+        let IndexScratch {
+            my_active,
+            my_next,
+            my_found,
+            my_candidates,
+        } = scratch;
+
+        // 后缀树找到的后缀直接写入缓存，没找到的后缀就是不匹配
+        my_found.clear();
+        self.my_postfix_trie.walk(
+            search_string.as_bytes().iter().rev().copied(),
+            my_active,
+            my_next,
+            |literal_index, terminal| my_found.push((literal_index as u32, terminal)),
+        );
+        for (literal_index, _) in my_found.iter() {
+            search_string
+                .my_postfix_cache
+                .set(*literal_index as usize, true);
+        }
+
+        my_found.clear();
+        self.my_prefix_trie.walk(
+            search_string.as_bytes().iter().copied(),
+            my_active,
+            my_next,
+            |literal_index, terminal| my_found.push((literal_index as u32, terminal)),
+        );
+
+        // 候选rule = (没有前缀的rule | 前缀匹配的rule) & 通过过滤器的rule
+        my_candidates.clone_from(&self.my_unprefixed_rules);
+        for (literal_index, terminal) in my_found.iter() {
+            search_string
+                .my_prefix_cache
+                .set(*literal_index as usize, true);
+            match &self.my_prefix_rules[*terminal as usize] {
+                RuleSet::Dense(mask) => filter::or_assign(my_candidates, mask),
+                RuleSet::Sparse(list) => {
+                    for i in list {
+                        my_candidates.set(*i as usize, true);
+                    }
+                }
+            }
+        }
+        filter::and_assign(my_candidates, includes);
+
+        for i in my_candidates.iter_ones() {
+            let rule = &rules[i];
+            if let Some(postfix) = rule.get_postfix()
+                && search_string.my_postfix_cache.get(postfix.get_index()) != Some(true)
+            {
+                continue;
+            }
+            if rule.matches(search_string) {
+                return Some(rule);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test_rule_index {
+    use super::*;
+    use crate::literal::LiteralArena;
+
+    fn create_rules(patterns: &[&str]) -> Vec<Rule> {
+        let mut literals = LiteralArena::new();
+        patterns
+            .iter()
+            .map(|pattern| {
+                crate::rule::create_rule(pattern.to_string(), 0, &mut literals).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_trie_walk() {
+        let mut trie = LiteralTrie::new();
+        let mut next_terminal = 0;
+        assert_eq!(trie.insert("ab".bytes(), 0, &mut next_terminal), 0);
+        assert_eq!(trie.insert("a?c".bytes(), 1, &mut next_terminal), 1);
+        assert_eq!(trie.insert("abd".bytes(), 2, &mut next_terminal), 2);
+        assert_eq!(trie.insert("ab".bytes(), 3, &mut next_terminal), 0);
+
+        let (mut active, mut next) = (Vec::new(), Vec::new());
+        let mut found = Vec::new();
+        trie.walk(
+            "abcx".bytes(),
+            &mut active,
+            &mut next,
+            |literal, terminal| found.push((literal, terminal)),
+        );
+        found.sort();
+        assert_eq!(found, vec![(0, 0), (1, 1)]);
+
+        found.clear();
+        trie.walk(
+            "xbc".bytes(),
+            &mut active,
+            &mut next,
+            |literal, terminal| found.push((literal, terminal)),
+        );
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn test_find_same_as_scan() {
+        let mut rules = create_rules(&[
+            "mozilla/5.0 (*android*chrome/*",
+            "mozilla/5.0 (*iphone*safari/*",
+            "mozilla/?.0 (*",
+            "*googlebot*",
+            "*bot",
+            "opera/9.?? *",
+        ]);
+        crate::user_agent_parser::get_ordered_rules(&mut rules);
+        let index = RuleIndex::new(&rules);
+        let mut scratch = IndexScratch::default();
+        let includes = BitVec::repeat(true, rules.len());
+        for user_agent in [
+            "Mozilla/5.0 (Linux; Android 9) Chrome/70 Mobile",
+            "Mozilla/5.0 (iPhone; CPU OS 12) Safari/604",
+            "Mozilla/4.0 (compatible)",
+            "Googlebot/2.1",
+            "somebot",
+            "Opera/9.80 (Windows)",
+            "curl/7.0",
+        ] {
+            let mut search_string = SearchableString::new(user_agent);
+            let expected = rules
+                .iter()
+                .find(|rule| rule.matches(&mut SearchableString::new(user_agent)))
+                .map(|rule| rule.get_pattern());
+            let found = index
+                .find(&rules, &mut search_string, &includes, &mut scratch)
+                .map(|rule| rule.get_pattern());
+            assert_eq!(found, expected, "{user_agent}");
+        }
+    }
+}
@@ -1,54 +1,80 @@
 use crate::literal::Literal;
-use bitvec::vec::BitVec;
-use std::{
-    fmt::{Debug, Formatter},
-};
-
-const EMPTY: Vec<u32> = Vec::new();
-const SINGLE_VALUES: [[u32; 1]; 1024] = get_single_values();
-const fn get_single_values() -> [[u32; 1]; 1024] {
-    let mut result = [[0; 1]; 1024];
-    let mut i = 0;
-    while i < 1024 {
-        result[i] = [i as u32];
-        i += 1;
-    }
-    result
-}
+use std::fmt::{Debug, Formatter};
 
+/*
+    可复用的搜索串：解析一个UA时只会重置内容和代数(generation)，不会重新分配内存。
+    每个缓存槽位都记录写入时的代数，代数不同即视为未知，所以不需要在每次解析前清空。
+*/
 #[derive(Clone, PartialEq, Eq)]
 pub struct SearchableString {
-    my_str: Vec<char>,
-    pub my_indices: Vec<Option<Vec<u32>>>,
+    my_str: Vec<u8>,
+    my_spans: Vec<Span>,
+    my_positions: Vec<u32>,
     pub my_prefix_cache: Cache,
     pub my_postfix_cache: Cache,
-    my_buffer: Vec<u32>,
+    my_generation: u32,
+}
+
+//记录某个literal在my_positions中的匹配位置区间
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+struct Span {
+    generation: u32,
+    start: u32,
+    len: u32,
 }
 
 impl Debug for SearchableString {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{:?}", self.my_indices)
+        writeln!(f, "{:?}", String::from_utf8_lossy(&self.my_str))
             .and_then(|_| writeln!(f, "{:?}", self.my_prefix_cache))
             .and_then(|_| writeln!(f, "{:?}", self.my_postfix_cache))
-            .and_then(|_| writeln!(f, "{:?}", &self.my_buffer))
+            .and_then(|_| writeln!(f, "{:?}", &self.my_positions))
     }
 }
 
-#[derive(Clone, PartialEq, Eq,Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Cache {
-    my_values: BitVec,
-    my_is_known: BitVec,
+    //每个槽位保存 generation << 1 | value
+    my_states: Vec<u32>,
+    my_generation: u32,
 }
 
-impl SearchableString {
-    pub fn new(string_value: String, max_index: usize) -> SearchableString {
-        let my_buffer = vec![0; string_value.len()];
+const MAX_GENERATION: u32 = u32::MAX >> 1;
+
+impl Default for SearchableString {
+    fn default() -> Self {
         SearchableString {
-            my_str: string_value.chars().collect(),
-            my_indices: vec![None; max_index],
-            my_postfix_cache: Cache::new(),
+            my_str: Vec::new(),
+            my_spans: Vec::new(),
+            my_positions: Vec::new(),
             my_prefix_cache: Cache::new(),
-            my_buffer,
+            my_postfix_cache: Cache::new(),
+            my_generation: 1,
+        }
+    }
+}
+
+impl SearchableString {
+    pub fn new(string_value: &str) -> SearchableString {
+        let mut result = SearchableString::default();
+        result.reset(string_value);
+        result
+    }
+
+    /// Replaces the contents with the ASCII-lower-cased `string_value` and forgets every cached
+    /// lookup, reusing the buffers of the previous value.
+    pub fn reset(&mut self, string_value: &str) {
+        self.my_str.clear();
+        self.my_str.extend_from_slice(string_value.as_bytes());
+        self.my_str.make_ascii_lowercase();
+        self.my_positions.clear();
+        self.my_prefix_cache.clear();
+        self.my_postfix_cache.clear();
+        if self.my_generation == MAX_GENERATION {
+            self.my_spans.fill(Span::default());
+            self.my_generation = 1;
+        } else {
+            self.my_generation += 1;
         }
     }
 
@@ -56,11 +82,14 @@ impl SearchableString {
         self.my_str.len()
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.my_str
+    }
+
     pub fn ends_with(&mut self, literal: &Literal) -> bool {
         let index = literal.get_index();
-        let cached = self.my_postfix_cache.get(index);
-        if cached.is_some() {
-            return cached.unwrap();
+        if let Some(cached) = self.my_postfix_cache.get(index) {
+            return cached;
         }
         let result = literal.matches(
             &self.my_str,
@@ -70,119 +99,106 @@ impl SearchableString {
         result
     }
 
-    pub fn get_indices(&mut self, literal: &Literal) -> &Vec<u32> {
+    pub fn get_indices(&mut self, literal: &Literal) -> &[u32] {
         let index = literal.get_index();
-        if self.my_indices[index].is_none() {
-            let values = self.find_indices(literal);
-            self.my_indices[index]= Some(values);
-        }
-        self.my_indices[index].as_ref().unwrap()
-    }
-
-    pub fn find_indices(&mut self, literal: &Literal) -> Vec<u32> {
-        let mut count = 0;
-        let s = literal.get_first_char();
-        for i in 0..self.my_str.len() {
-            // Check the first char for better performance and check the complete string
-            if (self.my_str[i] == s || s == '?') && literal.matches(&self.my_str, i as i32) {
-                // This index matches
-                self.my_buffer[count] = i as u32;
-                count += 1;
-            }
+        if index >= self.my_spans.len() {
+            self.my_spans.resize(index + 1, Span::default());
         }
-
-        // Check whether any match has been found
-        if count == 0 {
-            return EMPTY;
-        }
-
-        // Use an existing array
-        if count == 1 && self.my_buffer[0] < SINGLE_VALUES.len() as u32 {
-            let index = self.my_buffer[0];
-            return SINGLE_VALUES[index as usize].to_vec();
-        }
-
-        // Copy the values
-        let mut values = vec![0; count];
-        for i in 0..count {
-            values[i] = self.my_buffer[i];
+        if self.my_spans[index].generation != self.my_generation {
+            let start = self.my_positions.len();
+            literal.find_all(&self.my_str, &mut self.my_positions);
+            self.my_spans[index] = Span {
+                generation: self.my_generation,
+                start: start as u32,
+                len: (self.my_positions.len() - start) as u32,
+            };
         }
-        values
+        let span = self.my_spans[index];
+        &self.my_positions[span.start as usize..(span.start + span.len) as usize]
     }
 }
 
 pub fn starts_with(str: &mut SearchableString, literal: &Literal) -> bool {
     let index = literal.get_index();
-    let cached = str.my_prefix_cache.get(index);
-    if cached.is_some() {
-        return cached.unwrap();
+    if let Some(cached) = str.my_prefix_cache.get(index) {
+        return cached;
     }
     let result = literal.matches(&str.my_str, 0);
     str.my_prefix_cache.set(index, result);
     result
 }
 
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new()
+    }
+}
+
 impl Cache {
     pub fn new() -> Cache {
         Cache {
-            my_values: BitVec::new(),
-            my_is_known: BitVec::new(),
+            my_states: Vec::new(),
+            my_generation: 1,
         }
     }
 
-    pub fn get(self: &Self, index: usize) -> Option<bool> {
-        if self.my_values.get(index).map_or(false, |v| *v) {
-            return Some(true);
+    pub fn get(&self, index: usize) -> Option<bool> {
+        match self.my_states.get(index) {
+            Some(state) if state >> 1 == self.my_generation => Some(state & 1 == 1),
+            _ => None,
         }
-        if self.my_is_known.get(index).map_or(false, |v| *v) {
-            return Some(false);
-        }
-        None
     }
 
     pub fn set(&mut self, index: usize, flag: bool) {
-        bitset_set(&mut self.my_values, index, flag);
-        bitset_set(&mut self.my_is_known, index, true);
+        if index >= self.my_states.len() {
+            self.my_states.resize(index + 1, 0);
+        }
+        self.my_states[index] = (self.my_generation << 1) | flag as u32;
     }
-}
 
-fn bitset_set(bitset: &mut BitVec, index: usize, value: bool) {
-    if index >= bitset.len() {
-        bitset.resize(index + 1, false);
+    //使所有已缓存的结果失效
+    pub fn clear(&mut self) {
+        if self.my_generation == MAX_GENERATION {
+            self.my_states.fill(0);
+            self.my_generation = 1;
+        } else {
+            self.my_generation += 1;
+        }
     }
-    bitset.set(index, value);
 }
 
 #[cfg(test)]
 mod test_searchable_string {
     use super::*;
+    use crate::literal::LiteralArena;
     use ustr::Ustr;
 
     #[test]
     fn test_base() {
-        let abc = Literal::create_literal(Ustr::from("abc"));
-        let ab = Literal::create_literal(Ustr::from("ab"));
-        let string_value = "abababc".to_string();
-        let mut cache = SearchableString::new(string_value, 0);
-        assert_eq!(starts_with(&mut cache, &ab), true);
-        assert_eq!(starts_with(&mut cache, &abc), false);
+        let mut arena = LiteralArena::new();
+        let abc = arena.create_literal(Ustr::from("abc"));
+        let ab = arena.create_literal(Ustr::from("ab"));
+        let mut cache = SearchableString::new("abababc");
+        assert!(starts_with(&mut cache, &ab));
+        assert!(!starts_with(&mut cache, &abc));
         //test cache
-        assert_eq!(starts_with(&mut cache, &abc), false);
+        assert!(!starts_with(&mut cache, &abc));
 
-        assert_eq!(cache.ends_with(&abc), true);
-        assert_eq!(cache.ends_with(&ab), false);
+        assert!(cache.ends_with(&abc));
+        assert!(!cache.ends_with(&ab));
         //test cache
-        assert_eq!(cache.ends_with(&ab), false);
+        assert!(!cache.ends_with(&ab));
     }
 
     #[test]
     fn test_get_indices() {
-        let abc = Literal::create_literal(Ustr::from("abc"));
-        let ab = Literal::create_literal(Ustr::from("ab"));
-        let any_char = Literal::create_literal(Ustr::from("?ab"));
-        let no_match = Literal::create_literal(Ustr::from("aaaaaaaaaaaaaaaaaa"));
+        let mut arena = LiteralArena::new();
+        let abc = arena.create_literal(Ustr::from("abc"));
+        let ab = arena.create_literal(Ustr::from("ab"));
+        let any_char = arena.create_literal(Ustr::from("?ab"));
+        let no_match = arena.create_literal(Ustr::from("aaaaaaaaaaaaaaaaaa"));
 
-        let mut cache = SearchableString::new("abababc".to_string(), no_match.my_index + 1);
+        let mut cache = SearchableString::new("abababc");
         assert_eq!(vec![4; 1], *cache.get_indices(&abc));
         assert_eq!(vec![0, 2, 4], *cache.get_indices(&ab));
         assert_eq!(vec![1, 3], *cache.get_indices(&any_char));
@@ -194,16 +210,32 @@ mod test_searchable_string {
 
     #[test]
     fn test_get_buffer() {
-        let abc = Literal::create_literal(Ustr::from("abc"));
-        let ab = Literal::create_literal(Ustr::from("ab"));
-        let any_char = Literal::create_literal(Ustr::from("?ab"));
-        let no_match = Literal::create_literal(Ustr::from("aaaaaaaaaaaaaaaaaa"));
+        let mut arena = LiteralArena::new();
+        let abc = arena.create_literal(Ustr::from("abc"));
+        let ab = arena.create_literal(Ustr::from("ab"));
+        let any_char = arena.create_literal(Ustr::from("?ab"));
+        let no_match = arena.create_literal(Ustr::from("aaaaaaaaaaaaaaaaaa"));
+
+        let mut cache = SearchableString::new("abababc");
+        println!("{:?}", cache.get_indices(&abc));
+        println!("{:?}", cache.get_indices(&ab));
+        println!("{:?}", cache.get_indices(&any_char));
+        println!("{:?}", cache.get_indices(&no_match));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut arena = LiteralArena::new();
+        let ab = arena.create_literal(Ustr::from("ab"));
+        let mut cache = SearchableString::new("abababc");
+        assert_eq!(vec![0, 2, 4], *cache.get_indices(&ab));
+        assert!(starts_with(&mut cache, &ab));
 
-        let mut cache = SearchableString::new("abababc".to_string(), no_match.my_index + 1);
-        println!("{:?}", cache.find_indices(&abc));
-        println!("{:?}", cache.find_indices(&ab));
-        println!("{:?}", cache.find_indices(&any_char));
-        println!("{:?}", cache.find_indices(&no_match));
+        cache.reset("XAB");
+        assert_eq!(cache.get_size(), 3);
+        assert_eq!(vec![1], *cache.get_indices(&ab));
+        assert!(!starts_with(&mut cache, &ab));
+        assert!(cache.ends_with(&ab));
     }
 
     #[test]
@@ -216,5 +248,9 @@ mod test_searchable_string {
         assert_eq!(cache.get(1), None);
         cache.set(1, false);
         assert_eq!(cache.get(1), Some(false));
+
+        cache.clear();
+        assert_eq!(cache.get(0), None);
+        assert_eq!(cache.get(1), None);
     }
 }
@@ -1,146 +1,383 @@
-use crate::filter::{self, Filter, FilterType};
-use crate::literal::Literal;
-use crate::rule::Rule;
-use crate::{Capabilities, UserAgentParser, literal};
-use log::debug;
+use crate::filter::{self, Filter, FilterSet, FilterType, Mask};
+use crate::capabilities::CapabilityTable;
+use crate::error::ParseError;
+use crate::literal::LiteralArena;
+use crate::load_report::LoadContext;
+use crate::pattern_set::PatternSet;
+use crate::overlay::to_custom;
+use crate::rule::{self, Rule, normalize_pattern};
+use crate::rule_index::RuleIndex;
+use crate::{
+    BrowsCapField, Capabilities, Fallback, LoadPhase, MatchEngine, ParseOutcome, ParserOptions,
+    RulePriority, Scratch, UserAgentParser,
+};
+use bitvec::vec::BitVec;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::ops::Range;
 use std::time::Instant;
 use ustr::Ustr;
 
-pub const COMMON: [&str; 75] = [
-    "-",
-    "0",
-    "1",
-    "2",
-    "3",
-    "4",
-    "5",
-    "6",
-    "7",
-    "8",
-    "9",
-    "profile",
-    "player",
-    "compatible",
-    "android",
-    "google",
-    "tab",
-    "transformer",
-    "lenovo",
-    "micro",
-    "edge",
-    "safari",
-    "opera",
-    "chrome",
-    "firefox",
-    "msie",
-    "chromium",
-    "cpu os ",
-    "cpu iphone os ",
-    "windows nt ",
-    "mac os x ",
-    "linux",
-    "bsd",
-    "windows phone",
-    "iphone",
-    "pad",
-    "blackberry",
-    "nokia",
-    "alcatel",
-    "ucbrowser",
-    "mobile",
-    "ie",
-    "mercury",
-    "samsung",
-    "browser",
-    "wow64",
-    "silk",
-    "lunascape",
-    "crios",
-    "epiphany",
-    "konqueror",
-    "version",
-    "rv:",
-    "build",
-    "bot",
-    "like gecko",
-    "applewebkit",
-    "trident",
-    "mozilla",
-    "windows nt 4",
-    "windows nt 5.0",
-    "windows nt 5.1",
-    "windows nt 5.2",
-    "windows nt 6.0",
-    "windows nt 6.1",
-    "windows nt 6.2",
-    "windows nt 6.3",
-    "windows nt 10.0",
-    "android?4.0",
-    "android?4.1",
-    "android?4.2",
-    "android?4.3",
-    "android?4.4",
-    "android?2.3",
-    "android?5",
-];
-
-const FILTER_PREFIXES: [&str; 2] = ["mozilla/5.0", "mozilla/4"];
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::new());
+}
+
+//查找匹配rule的方式，由MatchEngine决定
+#[derive(Debug, Clone)]
+pub(crate) enum Matcher {
+    Rules(FilterSet, Option<RuleIndex>),
+    PatternSet(PatternSet),
+}
+
+impl Scratch {
+    pub fn new() -> Scratch {
+        Scratch::default()
+    }
+}
 
 impl  UserAgentParser {
-    pub fn new(mut rules: Vec<Rule>) -> UserAgentParser {
-        let timer=Instant::now();
-        get_ordered_rules(&mut rules);
-        let time=timer.elapsed();
-        debug!("order rules time:{:?}",time);
-        let my_filters = build_filters(&rules);
-        UserAgentParser {
+    /// Creates a parser from `rules` whose literals were all taken from `literals` and whose
+    /// capabilities index into `capabilities`.
+    pub fn new(
+        rules: Vec<Rule>,
+        literals: LiteralArena,
+        capabilities: CapabilityTable,
+    ) -> UserAgentParser {
+        let mut context = LoadContext::new(None, None);
+        let tiers = [0..0, 0..rules.len(), rules.len()..rules.len()];
+        let options = ParserOptions::default();
+        UserAgentParser::create(rules, tiers, literals, capabilities, &options, &mut context)
+            .unwrap()
+    }
+
+    //tiers按RulePriority依次列出各段rule，每段内按标准顺序排列
+    pub(crate) fn create(
+        mut rules: Vec<Rule>,
+        tiers: [Range<usize>; 3],
+        mut literals: LiteralArena,
+        capabilities: CapabilityTable,
+        options: &ParserOptions,
+        context: &mut LoadContext,
+    ) -> Result<UserAgentParser, ParseError> {
+        context.enter(LoadPhase::Sorting)?;
+        let timer = Instant::now();
+        context.report.duplicates = tiers
+            .iter()
+            .map(|tier| get_ordered_rules(&mut rules[tier.clone()]))
+            .sum();
+        context.report.sort_time = timer.elapsed();
+        let my_matcher = match options.engine {
+            MatchEngine::Rules => {
+                context.enter(LoadPhase::Filtering)?;
+                let timer = Instant::now();
+                let filters = build_filters(&rules, &mut literals, options.compressed_masks);
+                context.report.filter_time = timer.elapsed();
+                context.report.filter_count = filters.len();
+                let index = if options.rule_index {
+                    context.enter(LoadPhase::Indexing)?;
+                    let timer = Instant::now();
+                    let index = RuleIndex::new(&rules);
+                    context.report.index_time = timer.elapsed();
+                    Some(index)
+                } else {
+                    None
+                };
+                Matcher::Rules(filters, index)
+            }
+            MatchEngine::RegexSet => {
+                context.enter(LoadPhase::Indexing)?;
+                let timer = Instant::now();
+                let pattern_set = PatternSet::new(&rules)?;
+                context.report.index_time = timer.elapsed();
+                Matcher::PatternSet(pattern_set)
+            }
+        };
+        literals.freeze();
+        Ok(UserAgentParser {
             my_rules: rules,
-            my_filters: my_filters,
-        }
+            my_literals: literals,
+            my_capabilities: capabilities,
+            my_matcher,
+            my_tiers: tiers,
+            my_max_length: options.max_length,
+        })
     }
 
+    /// Parses `user_agent` using this thread's [`Scratch`].
+    ///
+    /// Patterns are matched against the user agent with ASCII letters lower-cased, byte by
+    /// byte: a `?` in a pattern matches exactly one byte. A non-ASCII character takes two to
+    /// four bytes in UTF-8, so it is matched by as many `?` and not by a single one.
     pub fn parse(&self, user_agent: &str) -> &Capabilities {
-        if user_agent.is_empty() {
-            return crate::capabilities::DEFAULT_CAPABILITIES.get().unwrap();
+        self.parse_outcome(user_agent).capabilities
+    }
+
+    /// Parses `user_agent` using caller-provided working memory, which is reused across calls.
+    pub fn parse_with(&self, scratch: &mut Scratch, user_agent: &str) -> &Capabilities {
+        self.parse_outcome_with(scratch, user_agent).capabilities
+    }
+
+    /// Like [`UserAgentParser::parse`], also telling whether fallback capabilities were used.
+    pub fn parse_outcome(&self, user_agent: &str) -> ParseOutcome<'_> {
+        SCRATCH.with(|scratch| self.parse_outcome_with(&mut scratch.borrow_mut(), user_agent))
+    }
+
+    /// Like [`UserAgentParser::parse_with`], also telling whether fallback capabilities were
+    /// used.
+    pub fn parse_outcome_with(&self, scratch: &mut Scratch, user_agent: &str) -> ParseOutcome<'_> {
+        let fallback = if let Some(fallback) = self.check_input(user_agent) {
+            fallback
+        } else if let Some(rule) = self.find_rule(scratch, user_agent) {
+            return ParseOutcome {
+                capabilities: self.my_capabilities.get(rule.get_capabilities()),
+                fallback: None,
+            };
+        } else {
+            Fallback::Unmatched
         };
-        let mut search_string = literal::get_searchable_string(user_agent.to_lowercase());
-        let includes = filter::filter(&mut search_string, &self.my_filters, self.my_rules.len());
-        for i in includes.iter_ones() {
-            let rule = &self.my_rules[i];
-            if rule.matches(&mut search_string) {
-                return rule.get_capabilities();
+        ParseOutcome {
+            capabilities: self.my_capabilities.get_fallback(fallback),
+            fallback: Some(fallback),
+        }
+    }
+
+    /// The normalized pattern of the rule matching `user_agent`, or `None` when it gets
+    /// fallback capabilities.
+    pub fn matched_pattern(&self, user_agent: &str) -> Option<String> {
+        if self.check_input(user_agent).is_some() {
+            return None;
+        }
+        SCRATCH.with(|scratch| {
+            self.find_rule(&mut scratch.borrow_mut(), user_agent)
+                .map(Rule::get_pattern)
+        })
+    }
+
+    /// Inserts a rule for `pattern`, written like a browscap pattern with `*` and `?` wildcards,
+    /// that gives `values`, like [`RuleOverlay::add`](crate::RuleOverlay::add) does while
+    /// loading. The rule goes where loading it from an overlay would put it, after the rules with
+    /// the same pattern, and the filter masks and the rule index are updated in place instead of
+    /// being rebuilt. With [`MatchEngine::RegexSet`] the patterns are compiled again.
+    ///
+    /// To update a parser other threads are reading, keep it in an [`Arc`](std::sync::Arc) and
+    /// go through [`Arc::make_mut`](std::sync::Arc::make_mut): it copies the parser while the
+    /// readers still hold it, so they keep parsing with the old rules until they take the new
+    /// `Arc`.
+    ///
+    /// # Cost
+    ///
+    /// The literals and capabilities loaded with the parser and the token automaton of the
+    /// filters are shared between copies, so a copy made by `make_mut` only duplicates the parts
+    /// an update changes: the rule list, the filter masks and the rule index. An update shifts
+    /// those by one position, which takes time linear in the number of rules but parses and
+    /// builds nothing again. The capabilities of inserted rules share one field layout, rebuilt
+    /// over the inserted rules only. With [`MatchEngine::RegexSet`] every pattern is compiled
+    /// again.
+    pub fn insert_rule(
+        &mut self,
+        pattern: &str,
+        priority: RulePriority,
+        values: &[(&'static BrowsCapField, &str)],
+    ) -> Result<(), ParseError> {
+        // 行号先占上，matcher更新成功后才追加capabilities
+        let capabilities = self.my_capabilities.len() as u32;
+        let pattern = normalize_pattern(pattern);
+        let rule = rule::create_rule(pattern, capabilities, &mut self.my_literals)?;
+        let tier = priority as usize;
+        let range = self.my_tiers[tier].clone();
+        let rule_pattern = rule.get_pattern();
+        // 与get_ordered_rules的顺序一致：size从大到小，size相同时按pattern，相同的pattern排在后面
+        let position = range.start
+            + self.my_rules[range].partition_point(|other| {
+                match other.get_size().cmp(&rule.get_size()) {
+                    Ordering::Greater => true,
+                    Ordering::Less => false,
+                    Ordering::Equal => {
+                        rule::compare_patterns(&other.get_pattern(), &rule_pattern).is_le()
+                    }
+                }
+            });
+        match &mut self.my_matcher {
+            Matcher::Rules(filters, index) => {
+                filters.insert_rule(position, &rule);
+                if let Some(index) = index {
+                    index.insert_rule(position, &rule);
+                }
+                self.my_rules.insert(position, rule);
             }
+            Matcher::PatternSet(pattern_set) => {
+                self.my_rules.insert(position, rule);
+                match PatternSet::new(&self.my_rules) {
+                    Ok(rebuilt) => *pattern_set = rebuilt,
+                    Err(error) => {
+                        self.my_rules.remove(position);
+                        return Err(error.into());
+                    }
+                }
+            }
+        }
+        self.my_capabilities.push_custom(&to_custom(values));
+        self.my_tiers[tier].end += 1;
+        for later in &mut self.my_tiers[tier + 1..] {
+            later.start += 1;
+            later.end += 1;
         }
-        return crate::capabilities::DEFAULT_CAPABILITIES.get().unwrap();
+        Ok(())
     }
-}
 
-fn build_filters(my_rules: &Vec<Rule>) -> Vec<Filter> {
-    let timer=Instant::now();
-    let mut result = Vec::new();
+    /// Removes every rule whose pattern is `pattern`, ignoring ASCII case, whether it came from
+    /// the browscap data or was inserted, and returns how many were removed. The filter masks
+    /// and the rule index are updated like in [`UserAgentParser::insert_rule`], at the same
+    /// cost.
+    pub fn remove_rule(&mut self, pattern: &str) -> usize {
+        // 用同样的方式创建rule，按prefix、suffixes、postfix比较
+        let Ok(wanted) = rule::create_rule(normalize_pattern(pattern), 0, &mut LiteralArena::new())
+        else {
+            return 0;
+        };
+        let mut removed = 0;
+        // 从后往前去掉，前面rule的下标不变
+        for position in (0..self.my_rules.len()).rev() {
+            if !self.my_rules[position].same_pattern(&wanted) {
+                continue;
+            }
+            if let Matcher::Rules(filters, index) = &mut self.my_matcher {
+                filters.remove_rule(position);
+                if let Some(index) = index {
+                    index.remove_rule(position);
+                }
+            }
+            self.my_rules.remove(position);
+            // rule的capabilities行留在表中，不影响其他行号
+            for tier in self.my_tiers.iter_mut() {
+                if tier.start > position {
+                    tier.start -= 1;
+                }
+                if tier.end > position {
+                    tier.end -= 1;
+                }
+            }
+            removed += 1;
+        }
+        if removed > 0
+            && let Matcher::PatternSet(pattern_set) = &mut self.my_matcher
+        {
+            *pattern_set = PatternSet::new(&self.my_rules)
+                .expect("the remaining patterns were compiled before");
+        }
+        removed
+    }
 
-    for pattern in FILTER_PREFIXES {
-        let pattern = Ustr::from(pattern);
-        let literal = Literal::create_literal(pattern);
-        let mask = filter::create_prefix_masker(my_rules, pattern);
-        result.push(Filter::new(FilterType::Prefix(literal), mask));
+    /// Number of distinct literals of this parser; their indices are `0..get_literal_count()`.
+    pub fn get_literal_count(&self) -> usize {
+        self.my_literals.len()
     }
-    // Build filters for specific contains constraints
-    for common in COMMON {
-        let common = Ustr::from(common);
-        let literal = Literal::create_literal(common);
-        let mask = filter::create_contains_masker(my_rules, common);
-        result.push(Filter::new(FilterType::Contains(literal), mask));
+
+    //不进行匹配的UA对应的Fallback
+    fn check_input(&self, user_agent: &str) -> Option<Fallback> {
+        if user_agent.is_empty() {
+            Some(Fallback::Empty)
+        } else if self.my_max_length.is_some_and(|max_length| user_agent.len() > max_length) {
+            Some(Fallback::TooLong)
+        } else {
+            None
+        }
     }
-    let time=timer.elapsed();
-    debug!("build filters time:{:?}",time);
-    result
+
+    //优先级最高的匹配rule
+    fn find_rule(&self, scratch: &mut Scratch, user_agent: &str) -> Option<&Rule> {
+        let search_string = &mut scratch.my_search;
+        search_string.reset(user_agent);
+        let (filters, index) = match &self.my_matcher {
+            Matcher::Rules(filters, index) => (filters, index),
+            Matcher::PatternSet(pattern_set) => {
+                return pattern_set.find(search_string.as_bytes()).map(|i| &self.my_rules[i]);
+            }
+        };
+        filter::filter(
+            search_string,
+            filters,
+            self.my_rules.len(),
+            &mut scratch.my_found_tokens,
+            &mut scratch.my_includes,
+            &mut scratch.my_sparse_excludes,
+        );
+        if let Some(index) = index {
+            return index.find(
+                &self.my_rules,
+                search_string,
+                &scratch.my_includes,
+                &mut scratch.my_index,
+            );
+        }
+        scratch
+            .my_includes
+            .iter_ones()
+            .map(|i| &self.my_rules[i])
+            .find(|rule| rule.matches(search_string))
+    }
+}
+
+fn build_filters(
+    my_rules: &[Rule],
+    literals: &mut LiteralArena,
+    compress_masks: bool,
+) -> FilterSet {
+    let prefix_tokens = filter::select_prefix_tokens(my_rules);
+    let prefix_masks = create_masks(
+        &prefix_tokens,
+        |token| filter::create_prefix_masker(my_rules, token),
+        compress_masks,
+    );
+    let prefix_filters = prefix_tokens
+        .into_iter()
+        .zip(prefix_masks)
+        .map(|(pattern, mask)| {
+            let literal = literals.create_literal(pattern);
+            Filter::new(FilterType::Prefix(Box::new(literal)), mask)
+        })
+        .collect();
+    // Build filters for the most frequent contains constraints of the loaded rules
+    let contains_tokens = filter::select_contains_tokens(my_rules);
+    let contains_masks = create_masks(
+        &contains_tokens,
+        |token| filter::create_contains_masker(my_rules, token),
+        compress_masks,
+    );
+    let contains_filters = contains_tokens
+        .into_iter()
+        .zip(contains_masks)
+        .map(|(token, mask)| Filter::new(FilterType::Contains(token), mask))
+        .collect();
+    FilterSet::new(prefix_filters, contains_filters, my_rules.len())
+}
+
+//每个token的mask只依赖rules，开启parallel时并行创建，结果顺序与tokens一致
+fn create_masks(
+    tokens: &[Ustr],
+    masker: impl Fn(Ustr) -> BitVec + Sync,
+    compress_masks: bool,
+) -> Vec<Mask> {
+    #[cfg(feature = "parallel")]
+    let tokens = tokens.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let tokens = tokens.iter();
+    tokens
+        .map(|token| Mask::new(masker(*token), compress_masks))
+        .collect()
 }
-fn get_ordered_rules(rules: &mut Vec<Rule>) {
-     let total_timer = Instant::now();
-    let mut pattern_build_count = 0;
+
+//返回pattern与其他rule重复的rule数
+pub(crate) fn get_ordered_rules(rules: &mut [Rule]) -> usize {
+    let mut duplicates = 0;
     
     // 阶段1：快速按size排序
+    //两种排序都是稳定的，并行与否得到的顺序相同
+    #[cfg(feature = "parallel")]
+    rules.par_sort_by_key(|r| std::cmp::Reverse(r.get_size()));
+    #[cfg(not(feature = "parallel"))]
     rules.sort_by_key(|r| std::cmp::Reverse(r.get_size()));
     
     // 阶段2：只处理size相同的组
@@ -155,31 +392,33 @@ fn get_ordered_rules(rules: &mut Vec<Rule>) {
         
         if j - i > 1 {
             // 关键优化：组内预计算Pattern
-            let patterns: Vec<String> = rules[i..j]
-                .iter()
-                .map(|r| {
-                    pattern_build_count += 1;
-                    r.get_pattern()
-                })
-                .collect();
+            #[cfg(feature = "parallel")]
+            let patterns: Vec<String> = rules[i..j].par_iter().map(Rule::get_pattern).collect();
+            #[cfg(not(feature = "parallel"))]
+            let patterns: Vec<String> = rules[i..j].iter().map(Rule::get_pattern).collect();
             
             // 使用预计算的patterns进行排序，但记录原始索引
             let mut indexed_patterns: Vec<(usize, &String)> = 
                 patterns.iter().enumerate().collect();
             
-            indexed_patterns.sort_by(|a, b| a.1.cmp(b.1));
+            #[cfg(feature = "parallel")]
+            indexed_patterns.par_sort_by(|a, b| rule::compare_patterns(a.1, b.1));
+            #[cfg(not(feature = "parallel"))]
+            indexed_patterns.sort_by(|a, b| rule::compare_patterns(a.1, b.1));
+            duplicates += indexed_patterns.windows(2).filter(|w| w[0].1 == w[1].1).count();
             
-            // 获取排序后的索引顺序
-            let mut sorted_indices: Vec<usize> = 
-                indexed_patterns.into_iter().map(|(idx, _)| idx).collect();
+            // 每条rule排序后的目标位置
+            let mut targets: Vec<usize> = vec![0; j - i];
+            for (pos, (idx, _)) in indexed_patterns.into_iter().enumerate() {
+                targets[idx] = pos;
+            }
             
-            // 原地重新排列rules（使用交换）
+            // 原地重新排列rules（使用交换）：把当前位置的rule换到它的目标位置，直到换来的rule就属于这里
             for pos in 0..(j - i) {
-                let current = pos;
-                while sorted_indices[current] != current {
-                    let target = sorted_indices[current];
-                    rules.swap(i + current, i + target);
-                    sorted_indices.swap(current, target);
+                while targets[pos] != pos {
+                    let target = targets[pos];
+                    rules.swap(i + pos, i + target);
+                    targets.swap(pos, target);
                 }
             }
         }
@@ -187,6 +426,27 @@ fn get_ordered_rules(rules: &mut Vec<Rule>) {
         i = j;
     }
     
-    debug!("总时间: {:?}", total_timer.elapsed());
-    debug!("最终Pattern构建次数: {}", pattern_build_count);
+    duplicates
+}
+
+#[cfg(test)]
+mod test_user_agent_parser {
+    use super::*;
+    use crate::rule;
+
+    #[test]
+    fn test_ordered_rules() {
+        let mut literals = LiteralArena::new();
+        let mut rules: Vec<Rule> = ["*b*", "*c*", "longer*", "*a*", "a?c", "*c*", "abc", "x"]
+            .iter()
+            .map(|pattern| rule::create_rule(pattern.to_string(), 0, &mut literals).unwrap())
+            .collect();
+        assert_eq!(get_ordered_rules(&mut rules), 1);
+        let patterns: Vec<String> = rules.iter().map(Rule::get_pattern).collect();
+        // 同样长时*和?排在字面字节之后
+        assert_eq!(
+            patterns,
+            vec!["longer*", "abc", "a?c", "*a*", "*b*", "*c*", "*c*", "x"]
+        );
+    }
 }
@@ -8,7 +8,7 @@ mod bitvec_test {
     #[cfg(test)]
     pub fn bitset_base64(bit_set: &BitVec) -> String {
         let bit_len = bit_set.len();
-        let byte_len = (bit_len + 7) / 8;
+        let byte_len = bit_len.div_ceil(8);
         let mut bytes = vec![0u8; byte_len];
 
         for i in 0..bit_len {
@@ -30,4 +30,13 @@ mod bitvec_test {
         STANDARD.encode_string(&bytes[..last_non_zero], &mut str);
         str
     }
+
+    #[test]
+    fn test_bitset_base64() {
+        let mut bit_set = BitVec::new();
+        bit_set.resize(24, false);
+        bit_set.set(0, true);
+        bit_set.set(9, true);
+        assert_eq!(bitset_base64(&bit_set), STANDARD.encode([1u8, 2u8]));
+    }
 }
@@ -0,0 +1,181 @@
+#![cfg(feature = "cli")]
+
+#[cfg(test)]
+mod cli_test {
+    use std::io::Write;
+    use std::process::{Command, Output, Stdio};
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+
+    fn run(args: &[&str], stdin: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_browscap"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    #[test]
+    fn test_arguments() {
+        let output = run(&["-f", "browser,platform_version", "-o", "csv", IPHONE], "");
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], "user_agent,browser,platform_version");
+        assert_eq!(lines[1], format!("\"{IPHONE}\",Safari,10.2"));
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_stdin() {
+        let input = format!("{IPHONE}\r\nnot a browser\n");
+        let output = run(
+            &["--fields", "browser", "--format", "json", "--explain"],
+            &input,
+        );
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!(
+            "{{\"user_agent\":\"{IPHONE}\",\"browser\":\"Safari\",\"pattern\":\"mozilla/"
+        )));
+        assert_eq!(
+            lines[1],
+            "{\"user_agent\":\"not a browser\",\"browser\":\"Default Browser\",\"pattern\":null}"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let output = run(&["--fields", "no_such_field", IPHONE], "");
+        assert!(!output.status.success());
+        let output = run(&["--data", "does/not/exist.csv", IPHONE], "");
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("does/not/exist.csv"), "{stderr}");
+        let output = run(&["--data", "browscap.snapshot", IPHONE], "");
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("only browscap CSV and INI files"), "{stderr}");
+    }
+
+    #[test]
+    fn test_ini_data() {
+        let file_name = format!("browscap-cli-{}.ini", std::process::id());
+        let path = std::env::temp_dir().join(file_name);
+        let ini = "[Safari]\nBrowser=\"Safari\"\n\n\
+                   [Mozilla/5.0 (iPhone*]\nParent=\"Safari\"\nPlatform=\"iOS\"\n";
+        std::fs::write(&path, ini).unwrap();
+        let data = path.to_str().unwrap();
+        let output = run(&["--data", data, "-f", "browser,platform", "-o", "csv", IPHONE], "");
+        std::fs::remove_file(&path).unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout.lines().nth(1), Some(format!("\"{IPHONE}\",Safari,iOS").as_str()));
+    }
+
+    #[test]
+    fn test_log() {
+        let input = format!(
+            "203.0.113.7 - - [10/Oct/2026:13:55:36 +0000] \"GET / HTTP/1.1\" 200 512 \"-\" \"{IPHONE}\"\nbroken\n"
+        );
+        let output = run(&["log", "--fields", "browser"], &input);
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(&format!(
+            "\"user_agent\":\"{IPHONE}\",\"browser\":\"Safari\"}}"
+        )));
+        assert_eq!(lines[1], "{\"line\":\"broken\",\"user_agent\":null}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("1 of 2 lines"), "{stderr}");
+
+        let input = format!("{IPHONE} | 200\n");
+        let output = run(
+            &["log", "-f", "browser", "-l", "$http_user_agent | $status"],
+            &input,
+        );
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.ends_with("\"browser\":\"Safari\"}\n"), "{stdout}");
+
+        let output = run(&["log", "--log-format", "$a$b"], "");
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_report() {
+        let input = format!("{IPHONE}\n\n{IPHONE}\nnot a browser\n");
+        let output = run(
+            &[
+                "report",
+                "-b",
+                "browser",
+                "-b",
+                "browser+platform",
+                "-t",
+                "1",
+                "-o",
+                "csv",
+            ],
+            &input,
+        );
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "key,value,count,percent",
+                "browser,Safari,2,66.7",
+                "browser,(other),1,33.3",
+                "browser+platform,Safari iOS,2,66.7",
+                "browser+platform,(other),1,33.3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let data = std::fs::read_to_string("browscap_sorted.csv").unwrap();
+        let path =
+            std::env::temp_dir().join(format!("browscap_cli_diff_{}.csv", std::process::id()));
+        std::fs::write(&path, data.replace("\"Safari\"", "\"Mobile Safari\"")).unwrap();
+        let input = format!("{IPHONE}\nnot a browser\n");
+        let output = run(
+            &[
+                "diff",
+                "browscap_sorted.csv",
+                path.to_str().unwrap(),
+                "-f",
+                "browser,platform",
+                "-o",
+                "tsv",
+            ],
+            &input,
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "field\told\tnew\tcount\tpercent\texamples",
+                &format!("browser\tSafari\tMobile Safari\t1\t50.0\t{IPHONE}"),
+            ]
+        );
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("1 of 2 user agents changed"), "{stderr}");
+    }
+}
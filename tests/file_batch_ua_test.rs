@@ -1,23 +1,18 @@
 #[cfg(test)]
 mod file_batch_ua_test {
     use browscap_rs::Capabilities;
-    use log::debug;
     use std::fs::File;
-    use std::io::{stdin, BufRead, BufReader, Read};
-    use std::time::Instant;
+    use std::io::{stdin, BufRead, BufReader};
 
     #[test]
     fn test_load_parser_file() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Debug)
-            .init();
+            .try_init();
         let file = File::open("useragents.txt").unwrap();
         let reader = BufReader::new(file);
         let parser = browscap_rs::load_parser_default().unwrap();
-        let mut i = 0;
         for line in reader.lines() {
-            i += 1;
-            // debug!("处理第{}行",i);
             let line = line.unwrap();
             if line.starts_with("#") {
                 continue;
@@ -26,10 +21,7 @@ mod file_batch_ua_test {
             if properties.len() < 5 {
                 continue;
             }
-            let timer=Instant::now();
             let capabilities: &Capabilities = parser.parse(properties.get(5).unwrap());
-            let time=timer.elapsed();
-            // debug!("解析一条用时：{:?}",time);
             let mut y: usize = 0;
             assert_eq!(
                 *properties.get(y).unwrap(),
@@ -60,9 +52,9 @@ mod file_batch_ua_test {
 
      #[test]
     fn test_memory() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Debug)
-            .init();
+            .try_init();
         let mut buf=String::new();
         stdin().read_line(&mut buf).unwrap();
         let file = File::open("useragents.txt").unwrap();
@@ -71,9 +63,7 @@ mod file_batch_ua_test {
         stdin().read_line(&mut buf).unwrap();
         let parser = browscap_rs::load_parser_default().unwrap();
         stdin().read_line(&mut buf).unwrap();
-        let mut i = 0;
         for line in reader.lines() {
-            i += 1;
             let line = line.unwrap();
             if line.starts_with("#") {
                 continue;
@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod ini_file_test {
+    use browscap_rs::{FieldSet, UserAgentParser};
+    use std::collections::BTreeSet;
+    use std::fmt::Write;
+    use std::fs;
+
+    // 把browscap_sorted.csv的每条记录写成一节，Parent指向的节追加在最后，不带任何键
+    fn csv_as_ini() -> String {
+        let mut reader = csv::Reader::from_path("browscap_sorted.csv").unwrap();
+        let headers = reader.headers().unwrap().clone();
+        let mut ini = String::from(";;; browscap_sorted.csv\n[GJK_Browscap_Version]\nVersion=0\n");
+        let mut parents = BTreeSet::new();
+        for record in reader.records() {
+            let record = record.unwrap();
+            writeln!(ini, "\n[{}]", &record[0]).unwrap();
+            for (key, value) in headers.iter().zip(record.iter()).skip(1) {
+                writeln!(ini, "{}=\"{value}\"", key.trim()).unwrap();
+            }
+            parents.insert(record[3].to_string());
+        }
+        for parent in parents.iter().filter(|parent| !parent.is_empty()) {
+            writeln!(ini, "\n[{parent}]").unwrap();
+        }
+        ini
+    }
+
+    #[test]
+    fn test_same_as_csv() {
+        let path = std::env::temp_dir().join(format!("browscap-{}.ini", std::process::id()));
+        fs::write(&path, csv_as_ini()).unwrap();
+        let (ini, report) = UserAgentParser::builder()
+            .fields(FieldSet::FULL)
+            .file(&path)
+            .build_with_report()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+        let csv = UserAgentParser::builder().fields(FieldSet::FULL).build().unwrap();
+
+        let user_agents = fs::read_to_string("useragents.txt").unwrap();
+        for user_agent in user_agents.lines().filter_map(|line| line.split("    ").nth(5)) {
+            assert_eq!(ini.parse(user_agent), csv.parse(user_agent), "{user_agent}");
+        }
+    }
+}
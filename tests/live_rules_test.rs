@@ -0,0 +1,171 @@
+#[cfg(test)]
+mod live_rules_test {
+    use browscap_rs::{
+        BROWSER, BROWSER_TYPE, IS_CRAWLER, MatchEngine, ParserBuilder, RuleOverlay, RulePriority,
+        UserAgentParser,
+    };
+    use std::fs;
+    use std::sync::Arc;
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+    const APP: &str = "ExampleApp/3.1 (Linux; Tizen 4.0)";
+    const PARTNER_BOT: &str = "Mozilla/5.0 (compatible; PartnerBot/1.0)";
+    const OTHER_APP: &str = "OtherApp/3.1 (Linux; Tizen 4.0)";
+
+    type Insert = (&'static str, RulePriority, &'static str);
+
+    // 最后两条一样长，只差一个*，插入时要和全量构建一样把字面的排在前面
+    const INSERTS: [Insert; 6] = [
+        ("ExampleApp/*", RulePriority::Before, "ExampleApp"),
+        ("*partnerbot*", RulePriority::After, "PartnerBot"),
+        ("Mozilla/5.0 (*iPhone*", RulePriority::Ordered, "Phone"),
+        ("*android*chrome/*", RulePriority::Ordered, "Chrome"),
+        ("OtherApp/*.1 (Linux*", RulePriority::Ordered, "OtherApp"),
+        ("OtherApp/3.1 (Linux*", RulePriority::Ordered, "OtherApp 3"),
+    ];
+
+    fn user_agents() -> Vec<String> {
+        let mut user_agents: Vec<String> = fs::read_to_string("useragents.txt")
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        user_agents.extend([IPHONE, APP, PARTNER_BOT, OTHER_APP].map(str::to_string));
+        user_agents
+    }
+
+    fn assert_same(parser: &UserAgentParser, rebuilt: &UserAgentParser, user_agents: &[String]) {
+        for user_agent in user_agents {
+            assert_eq!(
+                parser.matched_pattern(user_agent),
+                rebuilt.matched_pattern(user_agent),
+                "{user_agent}"
+            );
+            assert_eq!(
+                parser.parse(user_agent),
+                rebuilt.parse(user_agent),
+                "{user_agent}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_as_rebuild() {
+        let user_agents = user_agents();
+        let configs: [fn() -> ParserBuilder; 3] = [
+            UserAgentParser::builder,
+            || UserAgentParser::builder().rule_index(true),
+            || {
+                UserAgentParser::builder()
+                    .rule_index(true)
+                    .compressed_masks(true)
+            },
+        ];
+        for config in configs {
+            let mut parser = config().fields(vec![&IS_CRAWLER]).build().unwrap();
+            let removed = parser.matched_pattern(IPHONE).unwrap();
+            let mut overlay = RuleOverlay::new().suppress(&removed);
+            for (pattern, priority, browser) in INSERTS {
+                let values = [(&BROWSER, browser), (&IS_CRAWLER, "false")];
+                parser.insert_rule(pattern, priority, &values).unwrap();
+                overlay = overlay.add(pattern, priority, &values);
+            }
+            assert_eq!(parser.remove_rule(&removed.to_uppercase()), 1);
+            let rebuilt = config()
+                .fields(vec![&IS_CRAWLER])
+                .overlay(overlay)
+                .build()
+                .unwrap();
+            assert_same(&parser, &rebuilt, &user_agents);
+            assert_eq!(parser.parse(APP).get_browser(), Some("ExampleApp"));
+            assert_eq!(parser.parse(OTHER_APP).get_browser(), Some("OtherApp 3"));
+            assert_ne!(parser.matched_pattern(IPHONE), Some(removed.clone()));
+            assert_eq!(
+                parser.parse(PARTNER_BOT).get_value(&BROWSER_TYPE),
+                Some("Unknown")
+            );
+
+            // 去掉插入的rule后与原来的parser相同
+            for (pattern, _, _) in INSERTS {
+                assert_eq!(parser.remove_rule(pattern), 1);
+            }
+            let original = config()
+                .fields(vec![&IS_CRAWLER])
+                .overlay(RuleOverlay::new().suppress(&removed))
+                .build()
+                .unwrap();
+            assert_same(&parser, &original, &user_agents);
+        }
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut parser = UserAgentParser::builder().build().unwrap();
+        assert!(parser.insert_rule("*", RulePriority::Before, &[]).is_err());
+        assert!(parser.insert_rule("", RulePriority::Before, &[]).is_err());
+        assert_eq!(parser.remove_rule("*"), 0);
+        assert_eq!(parser.remove_rule("no such pattern"), 0);
+        // 按pattern的结构比较，*的位置不同的不会被去掉
+        parser
+            .insert_rule("ab*cd", RulePriority::Before, &[(&BROWSER, "AB")])
+            .unwrap();
+        assert_eq!(parser.remove_rule("abc*d"), 0);
+        assert_eq!(parser.remove_rule("AB*CD"), 1);
+        assert_eq!(parser.parse(APP).get_browser(), Some("Default Browser"));
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut parser = Arc::new(UserAgentParser::builder().build().unwrap());
+        let reader = parser.clone();
+        Arc::make_mut(&mut parser)
+            .insert_rule(
+                "ExampleApp/*",
+                RulePriority::Before,
+                &[(&BROWSER, "ExampleApp")],
+            )
+            .unwrap();
+        assert_eq!(reader.parse(APP).get_browser(), Some("Default Browser"));
+        assert_eq!(parser.parse(APP).get_browser(), Some("ExampleApp"));
+
+        // 没有其他读者时就地修改
+        drop(reader);
+        let before = Arc::as_ptr(&parser);
+        assert_eq!(Arc::make_mut(&mut parser).remove_rule("exampleapp/*"), 1);
+        assert_eq!(Arc::as_ptr(&parser), before);
+        assert_eq!(parser.parse(APP).get_browser(), Some("Default Browser"));
+    }
+
+    #[test]
+    fn test_regex_set() {
+        let builder = || UserAgentParser::builder().engine(MatchEngine::RegexSet);
+        let mut parser = builder().build().unwrap();
+        let removed = parser.matched_pattern(IPHONE).unwrap();
+        parser
+            .insert_rule(
+                "ExampleApp/*",
+                RulePriority::Before,
+                &[(&BROWSER, "ExampleApp")],
+            )
+            .unwrap();
+        assert_eq!(parser.remove_rule(&removed), 1);
+        let rebuilt = builder()
+            .overlay(
+                RuleOverlay::new()
+                    .add(
+                        "ExampleApp/*",
+                        RulePriority::Before,
+                        &[(&BROWSER, "ExampleApp")],
+                    )
+                    .suppress(&removed),
+            )
+            .build()
+            .unwrap();
+        assert_same(
+            &parser,
+            &rebuilt,
+            &[IPHONE, APP, PARTNER_BOT].map(str::to_string),
+        );
+        assert_eq!(parser.parse(APP).get_browser(), Some("ExampleApp"));
+    }
+}
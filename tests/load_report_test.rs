@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod load_report_test {
+    use browscap_rs::{LoadPhase, ParseError, UserAgentParser};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_build_with_report() {
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let seen = phases.clone();
+        let (parser, report) = UserAgentParser::builder()
+            .rule_index(true)
+            .progress(move |progress| {
+                seen.lock().unwrap().push(progress.phase);
+                true
+            })
+            .build_with_report()
+            .unwrap();
+        let stats = parser.memory_stats();
+        assert!(report.records_read > 0);
+        assert_eq!(report.records_read, report.rules_created + report.skipped.len());
+        assert_eq!(report.rules_created, stats.rule_count);
+        assert_eq!(report.filter_count, stats.filter_count);
+        assert!(report.total_time >= report.read_time);
+
+        let mut phases = phases.lock().unwrap().clone();
+        phases.dedup();
+        assert_eq!(
+            phases,
+            vec![LoadPhase::Reading, LoadPhase::Sorting, LoadPhase::Filtering, LoadPhase::Indexing]
+        );
+    }
+
+    #[test]
+    fn test_cancel() {
+        let result = UserAgentParser::builder()
+            .progress(|progress| progress.phase == LoadPhase::Reading)
+            .build();
+        assert!(matches!(result, Err(ParseError::Cancelled)));
+
+        let result = UserAgentParser::builder()
+            .progress(|progress| progress.records_read < 1024)
+            .build_with_report();
+        assert!(matches!(result, Err(ParseError::Cancelled)));
+    }
+}
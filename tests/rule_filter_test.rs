@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod rule_filter_test {
+    use browscap_rs::{
+        BROWSER, Capabilities, FieldValue, IS_CRAWLER, IS_LITE_MODE, IS_SYNDICATION_READER,
+        RuleOverlay, RulePriority, UserAgentParser,
+    };
+    use std::fs;
+
+    fn not_crawler(capabilities: &Capabilities) -> bool {
+        capabilities.get_field(&IS_CRAWLER) != FieldValue::Value("true")
+    }
+
+    #[test]
+    fn test_rule_filter() {
+        let fields = vec![&IS_CRAWLER, &IS_SYNDICATION_READER];
+        let (parser, report) = UserAgentParser::builder()
+            .fields(fields.clone())
+            .build_with_report()
+            .unwrap();
+        let (filtered, filtered_report) = UserAgentParser::builder()
+            .fields(fields)
+            .rule_filter(not_crawler)
+            .rule_filter(|capabilities: &Capabilities| {
+                capabilities.get_field(&IS_SYNDICATION_READER) != FieldValue::Value("true")
+            })
+            .build_with_report()
+            .unwrap();
+        assert_eq!(report.filtered, 0);
+        assert!(filtered_report.filtered > 0);
+        assert_eq!(
+            filtered_report.records_read,
+            filtered_report.rules_created + filtered_report.filtered + filtered_report.skipped.len()
+        );
+        assert_eq!(filtered_report.rules_created, filtered.memory_stats().rule_count);
+        assert!(filtered.memory_stats().literal_count < parser.memory_stats().literal_count);
+        // 被过滤掉的rule不登记capabilities行
+        assert!(filtered.memory_stats().capability_count < parser.memory_stats().capability_count);
+
+        // 原来匹配的rule被保留时结果不变，否则不会得到被过滤掉的capabilities
+        let user_agents = fs::read_to_string("useragents.txt").unwrap();
+        let mut crawlers = 0;
+        for user_agent in user_agents.lines().filter_map(|line| line.split("    ").nth(5)) {
+            let capabilities = filtered.parse(user_agent);
+            assert!(not_crawler(capabilities), "{user_agent}");
+            let expected = parser.parse(user_agent);
+            if not_crawler(expected)
+                && expected.get_field(&IS_SYNDICATION_READER) != FieldValue::Value("true")
+            {
+                assert_eq!(
+                    filtered.matched_pattern(user_agent),
+                    parser.matched_pattern(user_agent),
+                    "{user_agent}"
+                );
+            } else {
+                crawlers += 1;
+            }
+        }
+        assert!(crawlers > 0);
+    }
+
+    #[test]
+    fn test_keep_lite_mode() {
+        let parser = UserAgentParser::builder()
+            .fields(vec![&IS_LITE_MODE])
+            .rule_filter(|capabilities: &Capabilities| {
+                capabilities.get_field(&IS_LITE_MODE) == FieldValue::Value("true")
+            })
+            .overlay(RuleOverlay::new().add(
+                "ExampleApp/*",
+                RulePriority::Before,
+                &[(&BROWSER, "ExampleApp")],
+            ))
+            .build()
+            .unwrap();
+        let full = UserAgentParser::builder()
+            .fields(vec![&IS_LITE_MODE])
+            .build()
+            .unwrap();
+        assert!(parser.memory_stats().rule_count < full.memory_stats().rule_count);
+        // overlay的rule不受过滤影响
+        let app = parser.parse("ExampleApp/3.1");
+        assert_eq!(app.get_browser(), Some("ExampleApp"));
+        assert_eq!(app.get_field(&IS_LITE_MODE), FieldValue::Unknown);
+
+        // 这些UA原来匹配的就是lite mode的rule，过滤后仍匹配同一条
+        for user_agent in [
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1",
+            "Mozilla/5.0 (Windows NT 6.1; WOW64; Trident/7.0; rv:11.0) like Gecko",
+        ] {
+            assert_eq!(full.parse(user_agent).get_field(&IS_LITE_MODE), FieldValue::Value("true"));
+            let outcome = parser.parse_outcome(user_agent);
+            assert!(outcome.fallback.is_none(), "{user_agent}");
+            assert_eq!(
+                outcome.capabilities.get_field(&IS_LITE_MODE),
+                FieldValue::Value("true")
+            );
+            assert_eq!(parser.matched_pattern(user_agent), full.matched_pattern(user_agent));
+        }
+    }
+}
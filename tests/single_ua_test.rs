@@ -1,12 +1,16 @@
 #[cfg(test)]
 mod single_ua_test {
-    use browscap_rs::{BrowsCapField, Capabilities, IS_TABLES};
+    use browscap_rs::{
+        BROWSER, BROWSER_MAKER, BrowsCapField, Capabilities, Fallback, FieldSet, FieldValue,
+        IS_CRAWLER, IS_TABLES, RENDERING_ENGINE_NAME, UserAgentParser,
+    };
+    use std::hash::{DefaultHasher, Hash, Hasher};
 
     #[test]
     fn test_load_parser_default() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Debug)
-            .init();
+            .try_init();
 
         let  parser = browscap_rs::load_parser_default();
         match parser {
@@ -15,16 +19,16 @@ mod single_ua_test {
                 print!("{:?}", capabilities)
             }
             Err(e) => {
-                println!("{}", e.to_string())
+                println!("{}", e)
             }
         }
     }
 
     #[test]
     fn test_load_parser_fields() {
-        env_logger::builder()
+        let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Debug)
-            .init();
+            .try_init();
         let my_fields: Vec<&BrowsCapField> = vec![&IS_TABLES];
         let parser = browscap_rs::load_parser_with_fields(my_fields);
         match parser {
@@ -33,8 +37,73 @@ mod single_ua_test {
                 print!("{:?}", capabilities)
             }
             Err(e) => {
-                println!("{}", e.to_string())
+                println!("{}", e)
             }
         }
     }
+
+    #[test]
+    fn test_load_field_set() {
+        let parser = browscap_rs::load_parser_with_fields(FieldSet::STANDARD - &IS_CRAWLER).unwrap();
+        let capabilities = parser.parse("Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1");
+        assert_eq!(capabilities.get_field(&BROWSER_MAKER), FieldValue::Value("Apple"));
+        assert_eq!(capabilities.get_field(&IS_CRAWLER), FieldValue::NotLoaded);
+        assert_eq!(capabilities.get_field(&RENDERING_ENGINE_NAME), FieldValue::NotLoaded);
+    }
+
+    #[test]
+    fn test_same_layout_between_parsers() {
+        let user_agent = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+        let parser = browscap_rs::load_parser_default().unwrap();
+        let reloaded = browscap_rs::load_parser_default().unwrap();
+        let capabilities = parser.parse(user_agent);
+        let reloaded_capabilities = reloaded.parse(user_agent);
+        assert_eq!(capabilities, reloaded_capabilities);
+        assert_eq!(hash(capabilities), hash(reloaded_capabilities));
+        assert_eq!(format!("{:?}", capabilities), format!("{:?}", reloaded_capabilities));
+    }
+
+    #[test]
+    fn test_parse_outcome() {
+        let user_agent = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+        let parser = UserAgentParser::builder()
+            .fallback_capabilities(Fallback::Empty, &[(&BROWSER, "No User Agent")])
+            .max_length(user_agent.len())
+            .build()
+            .unwrap();
+
+        let outcome = parser.parse_outcome(user_agent);
+        assert_eq!(outcome.fallback, None);
+        assert_eq!(outcome.capabilities, parser.parse(user_agent));
+
+        let outcome = parser.parse_outcome("");
+        assert_eq!(outcome.fallback, Some(Fallback::Empty));
+        assert_eq!(outcome.capabilities.get_field(&BROWSER), FieldValue::Value("No User Agent"));
+
+        let outcome = parser.parse_outcome(&format!("{user_agent} "));
+        assert_eq!(outcome.fallback, Some(Fallback::TooLong));
+        assert_eq!(outcome.capabilities.get_field(&BROWSER), FieldValue::Unknown);
+
+        let outcome = parser.parse_outcome("\u{1}");
+        assert_eq!(outcome.fallback, Some(Fallback::Unmatched));
+        assert_eq!(outcome.capabilities.get_browser(), Some("Default Browser"));
+    }
+
+    #[test]
+    fn test_matched_pattern() {
+        let user_agent = "Mozilla/5.0 (iPhone; CPU iPhone OS 10_2_1 like Mac OS X) AppleWebKit/602.4.6 (KHTML, like Gecko) Version/10.0 Mobile/14D27 Safari/602.1";
+        let parser = browscap_rs::load_parser_default().unwrap();
+        let pattern = parser.matched_pattern(user_agent).unwrap();
+        assert_eq!(pattern, user_agent.to_lowercase());
+        assert_eq!(parser.matched_pattern(""), None);
+        assert_eq!(parser.matched_pattern("\u{1}"), None);
+        assert_eq!(BrowsCapField::from_name("platform_version"), Some(&browscap_rs::PLATFORM_VERSION));
+        assert_eq!(BrowsCapField::from_name("nope"), None);
+    }
+
+    fn hash(capabilities: &Capabilities) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        capabilities.hash(&mut hasher);
+        hasher.finish()
+    }
 }
@@ -35,14 +35,14 @@ mod threads_test {
 
     fn parse_file_line(s: &str) -> (&str, &str, &str, &str, &str, &str) {
         let mut split = s.split("    ");
-        return (
+        (
             split.next().unwrap(),
             split.next().unwrap(),
             split.next().unwrap(),
             split.next().unwrap(),
             split.next().unwrap(),
             split.next().unwrap(),
-        );
+        )
     }
 
     fn load_file_line() -> Arc<Mutex<Vec<String>>> {